@@ -3,16 +3,31 @@ use std::net::SocketAddr;
 use anyhow::Context as _;
 use tokio::net::TcpStream;
 
-use crate::protocol::Username;
+use crate::protocol::{codec::Format, server::RoomCode, Username};
 
 pub mod input;
+pub mod session;
 pub mod states;
 pub mod ui;
 
-pub async fn connect(username: Username, host: SocketAddr) -> anyhow::Result<()> {
+pub async fn connect(
+    username: Username,
+    host: SocketAddr,
+    room: Option<RoomCode>,
+    formats: &[Format],
+) -> anyhow::Result<Option<String>> {
     let stream = TcpStream::connect(host)
         .await
         .with_context(|| format!("Failed to connect to address {}", host))?;
     // A client state machine
-    states::run(username, stream, tokio_util::sync::CancellationToken::new()).await
+    let session = session::SessionFile::new(&username, host);
+    states::run(
+        username,
+        room,
+        session,
+        stream,
+        formats,
+        tokio_util::sync::CancellationToken::new(),
+    )
+    .await
 }
@@ -5,7 +5,7 @@ use super::{
     states::{Chat, Connection, Context, Game, Home, Intro, Roles},
     ui::details::Statefulness,
 };
-use crate::protocol::{client, server, GamePhaseKind, Msg, RoleStatus, With};
+use crate::protocol::{client, server, GamePhaseKind, Msg, Password, RoleStatus, With};
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum InputMode {
     #[default]
@@ -59,26 +59,105 @@ where
     Ok(())
 }
 #[derive(Copy, Clone, PartialEq, Eq)]
+pub enum LoginCmd {
+    None,
+    Login,
+    Register,
+}
+pub const LOGIN_KEYS: &[(KeyEvent, LoginCmd)] = {
+    use LoginCmd as Cmd;
+    &[
+        (key!(KeyCode::Enter), Cmd::Login),
+        (
+            key!(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            Cmd::Register,
+        ),
+    ]
+};
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum IntroCmd {
     None,
-    Enter,
+    SelectPrev,
+    SelectNext,
+    JoinRoom,
+    CreateRoom,
+    WatchRoom,
+    StopWatching,
+    RefreshRooms,
 }
 pub const INTRO_KEYS: &[(KeyEvent, IntroCmd)] = {
     use IntroCmd as Cmd;
-    &[(key!(KeyCode::Enter), Cmd::Enter)]
+    &[
+        (key!(KeyCode::Up), Cmd::SelectPrev),
+        (key!(KeyCode::Down), Cmd::SelectNext),
+        (key!(KeyCode::Enter), Cmd::JoinRoom),
+        (key!(KeyCode::Char('n')), Cmd::CreateRoom),
+        (key!(KeyCode::Char('w')), Cmd::WatchRoom),
+        (key!(KeyCode::Esc), Cmd::StopWatching),
+        (key!(KeyCode::Char('r')), Cmd::RefreshRooms),
+    ]
 };
 impl Inputable for Context<Intro> {
     type State<'a> = &'a mut Connection<Intro>;
     fn handle_input(&mut self, event: &Event, state: Self::State<'_>) -> anyhow::Result<()> {
         if let Event::Key(key) = event {
-            if KeyEventKind::Press == key.kind {
+            if KeyEventKind::Press == key.kind && !self.state.is_logged() {
+                // the password prompt
+                match LOGIN_KEYS.get_action(key).unwrap_or(LoginCmd::None) {
+                    LoginCmd::None => {
+                        handle_main_input(event, state)?;
+                        self.state.password.handle_event(event);
+                    }
+                    _ if self.state.password.value().is_empty() => (),
+                    cmd => {
+                        let password = Password::from(self.state.password.value().to_string());
+                        state.tx.send(Msg::with(if cmd == LoginCmd::Register {
+                            client::IntroMsg::Register(self.username.clone(), password)
+                        } else {
+                            client::IntroMsg::Login(
+                                self.username.clone(),
+                                password,
+                                self.state.session.load(),
+                            )
+                        }))?;
+                    }
+                }
+            } else if KeyEventKind::Press == key.kind {
                 use IntroCmd as Cmd;
                 match INTRO_KEYS.get_action(key).unwrap_or(IntroCmd::None) {
                     Cmd::None => {
                         handle_main_input(event, state)?;
                     }
-                    Cmd::Enter => {
-                        state.tx.send(Msg::with(client::IntroMsg::EnterGame))?;
+                    Cmd::StopWatching => {
+                        if self.state.watching.take().is_some() {
+                            state.tx.send(Msg::with(client::IntroMsg::StopWatching))?;
+                            self.chat.messages.clear();
+                        }
+                    }
+                    // spectators only watch
+                    _ if self.state.watching.is_some() => (),
+                    Cmd::SelectPrev => self.state.rooms.prev(),
+                    Cmd::SelectNext => self.state.rooms.next(),
+                    Cmd::JoinRoom => {
+                        if let Some(room) = self.state.rooms.active() {
+                            state
+                                .tx
+                                .send(Msg::with(client::IntroMsg::JoinRoom(room.code)))?;
+                        }
+                    }
+                    Cmd::CreateRoom => {
+                        state.tx.send(Msg::with(client::IntroMsg::CreateRoom))?;
+                    }
+                    Cmd::WatchRoom => {
+                        if let Some(room) = self.state.rooms.active() {
+                            state
+                                .tx
+                                .send(Msg::with(client::IntroMsg::WatchRoom(room.code)))?;
+                        }
+                    }
+                    Cmd::RefreshRooms => {
+                        self.state.rejected = None;
+                        state.tx.send(Msg::with(client::IntroMsg::GetRooms))?;
                     }
                 }
             }
@@ -91,6 +170,14 @@ impl Inputable for Context<Intro> {
 pub enum HomeCmd {
     None,
     EnterChat,
+    FewerPlayers,
+    MorePlayers,
+    AddBot,
+    SelectPrev,
+    SelectNext,
+    Ready,
+    Kick,
+    Lock,
     StartRoles,
 }
 
@@ -99,6 +186,14 @@ pub const HOME_KEYS: &[(KeyEvent, HomeCmd)] = {
     &[
         (key!(KeyCode::Enter), Cmd::StartRoles),
         (key!(KeyCode::Char('e')), Cmd::EnterChat),
+        (key!(KeyCode::Left), Cmd::FewerPlayers),
+        (key!(KeyCode::Right), Cmd::MorePlayers),
+        (key!(KeyCode::Char('b')), Cmd::AddBot),
+        (key!(KeyCode::Up), Cmd::SelectPrev),
+        (key!(KeyCode::Down), Cmd::SelectNext),
+        (key!(KeyCode::Char('r')), Cmd::Ready),
+        (key!(KeyCode::Char('k')), Cmd::Kick),
+        (key!(KeyCode::Char('l')), Cmd::Lock),
     ]
 };
 
@@ -135,6 +230,43 @@ impl Inputable for Context<Home> {
                             Cmd::EnterChat => {
                                 self.chat.input_mode = InputMode::Editing;
                             }
+                            Cmd::FewerPlayers => {
+                                state.tx.send(Msg::with(client::HomeMsg::SetPlayerCount(
+                                    self.state.player_count.saturating_sub(1).max(1),
+                                )))?;
+                            }
+                            Cmd::MorePlayers => {
+                                state.tx.send(Msg::with(client::HomeMsg::SetPlayerCount(
+                                    (self.state.player_count + 1)
+                                        .min(server::MAX_PLAYER_COUNT as u8),
+                                )))?;
+                            }
+                            Cmd::AddBot => {
+                                state.tx.send(Msg::with(client::HomeMsg::AddBot))?;
+                            }
+                            Cmd::SelectPrev => {
+                                self.state.roster.prev();
+                            }
+                            Cmd::SelectNext => {
+                                self.state.roster.next();
+                            }
+                            Cmd::Ready => {
+                                state.tx.send(Msg::with(client::HomeMsg::Ready(
+                                    !self.state.is_ready(&self.username),
+                                )))?;
+                            }
+                            Cmd::Kick => {
+                                if let Some(p) = self.state.roster.active() {
+                                    state
+                                        .tx
+                                        .send(Msg::with(client::HomeMsg::Kick(p.name.clone())))?;
+                                }
+                            }
+                            Cmd::Lock => {
+                                state
+                                    .tx
+                                    .send(Msg::with(client::HomeMsg::Lock(!self.state.locked)))?;
+                            }
                             Cmd::StartRoles => {
                                 state.tx.send(Msg::with(client::HomeMsg::StartRoles))?;
                             }
@@ -0,0 +1,68 @@
+use std::{
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use crate::{
+    consts,
+    protocol::{SessionToken, Username},
+};
+
+// the session token of the last login to a server,
+// it is kept in a file to return to a started game after a restart of the client,
+// the file is in the cache directory of the user and only the user can read it
+#[derive(Debug, Default)]
+pub struct SessionFile(Option<PathBuf>);
+
+impl SessionFile {
+    pub fn new(username: &Username, host: SocketAddr) -> Self {
+        SessionFile(dirs::cache_dir().map(|dir| {
+            dir.join(consts::APPNAME).join(format!(
+                "{}-{}.session",
+                username,
+                host.to_string().replace([':', '[', ']'], "_")
+            ))
+        }))
+    }
+    pub fn load(&self) -> Option<SessionToken> {
+        let data = std::fs::read_to_string(self.0.as_ref()?).ok()?;
+        serde_json::from_str(&data).ok()
+    }
+    pub fn save(&self, token: &SessionToken) {
+        if let Some(file) = &self.0 {
+            if let Err(e) = serde_json::to_string(token)
+                .map_err(std::io::Error::from)
+                .and_then(|data| write_private(file, data.as_bytes()))
+            {
+                warn!(cause = %e, file = %file.display(), "Failed to save the session");
+            }
+        }
+    }
+}
+
+fn write_private(file: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut dir = std::fs::DirBuilder::new();
+    dir.recursive(true);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+        dir.mode(0o700);
+        options.mode(0o600);
+    }
+    if let Some(parent) = file.parent() {
+        dir.create(parent)?;
+    }
+    let mut f = options.open(file)?;
+    // the mode of an existing file is not changed by open
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        f.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    f.write_all(data)
+}
@@ -5,7 +5,7 @@ use futures::{SinkExt, StreamExt};
 use ratatui::widgets::ScrollbarState;
 use tokio::{net::TcpStream, sync::oneshot};
 use tokio_util::{
-    codec::{FramedRead, FramedWrite, LinesCodec},
+    codec::{FramedRead, FramedWrite},
     sync::CancellationToken,
 };
 use tracing::{error, info, warn};
@@ -13,6 +13,7 @@ use tui_input::Input;
 
 use super::{
     input::{InputMode, Inputable},
+    session::SessionFile,
     ui::{
         self,
         details::{StatefulList, Statefulness},
@@ -22,11 +23,17 @@ use super::{
 use crate::{
     game::{Card, Rank, Role, Suit},
     protocol::{
-        client, encode_message, server, GameContext, MessageDecoder, MessageReceiver, Msg,
-        RoleStatus, SendSocketMessage, TurnStatus, Username, With,
+        client,
+        codec::{Format, FrameCodec},
+        encode_message, server, GameContext, Hello, MessageDecoder, MessageReceiver, Msg,
+        RoleStatus, SendSocketMessage, TurnStatus, Username, Welcome, With,
     },
 };
 
+// the chat log comes in one frame, the server keeps only the last
+// 256 lines of a room, the rest is room for a longer --max-chat
+const MAX_SERVER_FRAME: usize = 8 * 1024 * 1024;
+
 pub type Tx<T> = tokio::sync::mpsc::UnboundedSender<T>;
 #[allow(dead_code)]
 pub type Rx<T> = tokio::sync::mpsc::UnboundedReceiver<T>;
@@ -64,10 +71,37 @@ pub struct Context<C> {
 #[derive(Debug, Default)]
 pub struct Intro {
     pub status: Option<server::LoginStatus>,
+    pub password: Input,
+    // a room to join right after login
+    pub room: Option<server::RoomCode>,
+    pub rooms: StatefulList<server::RoomInfo, Vec<server::RoomInfo>>,
+    pub rejected: Option<server::EnterRoomError>,
+    // a game of another room
+    pub watching: Option<server::GameView>,
+    pub session: SessionFile,
+}
+impl Intro {
+    pub fn is_logged(&self) -> bool {
+        matches!(self.status, Some(server::LoginStatus::Logged))
+    }
 }
 
 #[derive(Debug, Default)]
-pub struct Home {}
+pub struct Home {
+    pub room: server::RoomCode,
+    pub player_count: u8,
+    // the host is the first
+    pub roster: StatefulList<server::LobbyPlayer, Vec<server::LobbyPlayer>>,
+    pub locked: bool,
+}
+impl Home {
+    pub fn is_ready(&self, username: &Username) -> bool {
+        self.roster
+            .items
+            .iter()
+            .any(|p| p.ready && p.name == *username)
+    }
+}
 #[derive(Debug)]
 pub struct Roles {
     pub roles: StatefulList<RoleStatus, [RoleStatus; 4]>,
@@ -85,18 +119,28 @@ pub struct Game {
     pub phase: TurnStatus,
     pub attack_monster: Option<usize>,
     pub health: u16,
+    pub piles: server::PileSizes,
     pub abilities: StatefulList<Option<Rank>, [Option<Rank>; 3]>,
-    pub monsters: StatefulList<Option<Card>, [Option<Card>; 2]>,
+    pub monsters: StatefulList<Option<Card>, server::MonsterLine>,
+    pub game_over: Option<(server::GameOutcome, server::GameStats)>,
 }
 impl Game {
-    pub fn new(role: Suit, abilities: [Option<Rank>; 3], monsters: [Option<Card>; 2]) -> Self {
+    pub fn new(
+        role: Suit,
+        abilities: [Option<Rank>; 3],
+        monsters: server::MonsterLine,
+        health: u16,
+        piles: server::PileSizes,
+    ) -> Self {
         Game {
             role,
             attack_monster: None,
-            health: 36,
+            health,
+            piles,
             phase: TurnStatus::Wait,
             abilities: StatefulList::with_items(abilities),
             monsters: StatefulList::with_items(monsters),
+            game_over: None,
         }
     }
 }
@@ -104,9 +148,9 @@ use crate::protocol::details::impl_GameContextKind_from_state;
 impl_GameContextKind_from_state! {Intro Home Roles Game}
 
 macro_rules! done {
-    ($option:expr) => {
+    ($io:expr, $option:expr) => {
         match $option {
-            None => return Ok(()),
+            None => return Ok($io.farewell.take()),
             Some(x) => x,
         }
     };
@@ -118,19 +162,30 @@ pub struct ClientGameContext(pub GameContext<Intro, Home, Roles, Game>);
 
 pub async fn run(
     username: Username,
+    room: Option<server::RoomCode>,
+    session: SessionFile,
     mut stream: TcpStream,
+    formats: &[Format],
     cancel: CancellationToken,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<String>> {
     let (r, w) = stream.split();
+    let mut writer = FramedWrite::new(w, FrameCodec::new());
+    let mut reader = MessageDecoder::new(FramedRead::new(
+        r,
+        FrameCodec::with_max_frame(MAX_SERVER_FRAME),
+    ));
+    // before the terminal, a rejection is printed as a plain error
+    hello(&mut writer, &mut reader, formats).await?;
     let terminal = Arc::new(Mutex::new(
         TerminalHandle::new().context("Failed to create a terminal for the game")?,
     ));
     TerminalHandle::chain_panic_for_restore(Arc::downgrade(&terminal));
     let mut io = ClientIO {
-        writer: FramedWrite::new(w, LinesCodec::new()),
-        reader: MessageDecoder::new(FramedRead::new(r, LinesCodec::new())),
+        writer,
+        reader,
         input: crossterm::event::EventStream::new(),
         terminal,
+        farewell: None,
     };
 
     let mut context =
@@ -138,7 +193,11 @@ pub async fn run(
             Context {
                 username,
                 chat: Chat::default(),
-                state: Intro::default(),
+                state: Intro {
+                    room,
+                    session,
+                    ..Default::default()
+                },
             },
         );
 
@@ -147,26 +206,33 @@ pub async fn run(
             loop {
                 context = match context {
                     GameContext::Intro(mut i) => {
-                        io.writer.send(encode_message(Msg::with(client::IntroMsg::Login(i.username.clone())))).await?;
-                        match done!(run_context(&mut io, &mut i).await?){
-                               GameContext::Home(_)     => GameContext::Home(Context::<Home>::from(i)),
+                        match done!(io, run_context(&mut io, &mut i).await?){
+                               GameContext::Home(room)  => GameContext::Home(Context::<Home>::from((i, room))),
                                GameContext::Roles(role) => GameContext::Roles(Context::<Roles>::from((i, role))),
                                GameContext::Game(start) => GameContext::Game(Context::<Game>::from((i, start))),
                                _ => unreachable!(),
                         }
                     }
                     GameContext::Home(mut h) => {
-                        let role = done!(run_context(&mut io, &mut h).await?);
+                        let role = done!(io, run_context(&mut io, &mut h).await?);
                         GameContext::Roles(Context::<Roles>::from((h, role)))
                     }
                     GameContext::Roles(mut r) => {
-                        let start = done!(run_context(&mut io, &mut r).await?);
+                        let start = done!(io, run_context(&mut io, &mut r).await?);
                         GameContext::Game(Context::<Game>::from((r, start)))
 
                     }
                     GameContext::Game(mut g) => {
                         run_context(&mut io, &mut g).await?;
-                        return Ok(())
+                        // the outcome stays on the screen after the terminal is restored
+                        return Ok(io.farewell.take().or_else(|| g.state.game_over.map(|(outcome, stats)| format!(
+                            "{} Rounds: {}, monsters defeated: {}, monsters left: {}",
+                            match outcome {
+                                server::GameOutcome::Victory => "Victory! All monsters are defeated.",
+                                server::GameOutcome::Defeat => "Defeat! All players are fallen.",
+                            },
+                            stats.rounds, stats.monsters_defeated, stats.monsters_left
+                        ))))
                     }
                 }
             }
@@ -174,11 +240,36 @@ pub async fn run(
             res
         },
         _ = cancel.cancelled() => {
-            Ok(())
+            Ok(None)
         }
     }
 }
 
+async fn hello(
+    writer: &mut FramedWrite<WriteHalf<'_>, FrameCodec>,
+    reader: &mut MessageDecoder<FramedRead<ReadHalf<'_>, FrameCodec>>,
+    formats: &[Format],
+) -> anyhow::Result<()> {
+    let hello = Hello {
+        capabilities: Format::capabilities(formats),
+        ..Default::default()
+    };
+    writer
+        .send(encode_message(&hello))
+        .await
+        .context("failed to send a message to the socket")?;
+    let server = reader
+        .next::<Welcome>()
+        .await
+        .context("The server has closed the connection before the handshake")?
+        .context("The server doesn't support the handshake, it may be outdated")??;
+    let format = Format::negotiate(&hello.capabilities, &server.capabilities);
+    info!(version = server.protocol_version, app = %server.app_version, ?format, "Connected");
+    writer.encoder_mut().set_format(format);
+    reader.set_format(format);
+    Ok(())
+}
+
 async fn run_context<S>(
     io: &mut ClientIO<'_>,
     visitor: &mut Context<S>,
@@ -233,10 +324,26 @@ where
                             match msg {
                                 SharedMsg::Pong => {
 
+                                }
+                                SharedMsg::Ping => {
+                                    io.writer.send(encode_message(
+                                        Msg::<client::SharedMsg, client::IntroMsg>::Shared(client::SharedMsg::Pong),
+                                    )).await.context("failed to send a message to the socket")?;
                                 }
                                 SharedMsg::Logout =>  {
                                         break
                                 },
+                                SharedMsg::ServerShutdown { reason } => {
+                                    info!(%reason, "The server has closed the connection");
+                                    io.farewell = Some(format!("The server has closed the connection: {}", reason));
+                                    break
+                                },
+                                SharedMsg::RoomCrashed => {
+                                    anyhow::bail!("The room has stopped on the server, log in again to continue a started game");
+                                },
+                                SharedMsg::Kicked => {
+                                    anyhow::bail!("The host has removed you from the room");
+                                },
                                 SharedMsg::Chat(line) => {
                                     visitor.chat.messages.push(line);
 
@@ -267,8 +374,8 @@ where
     Ok(None)
 }
 
-impl From<Context<Intro>> for Context<Home> {
-    fn from(intro: Context<Intro>) -> Self {
+impl From<(Context<Intro>, server::RoomCode)> for Context<Home> {
+    fn from((intro, room): (Context<Intro>, server::RoomCode)) -> Self {
         assert!(
             intro.state.status.is_some()
                 && matches!(intro.state.status.unwrap(), server::LoginStatus::Logged),
@@ -277,7 +384,11 @@ impl From<Context<Intro>> for Context<Home> {
         Context::<Home> {
             username: intro.username,
             chat: intro.chat,
-            state: Home::default(),
+            state: Home {
+                room,
+                player_count: server::DEFAULT_PLAYER_COUNT as u8,
+                ..Default::default()
+            },
         }
     }
 }
@@ -307,7 +418,13 @@ impl From<(Context<Intro>, Option<Role>)> for Context<Roles> {
 impl From<StartGame> for Game {
     #[inline]
     fn from(start: StartGame) -> Self {
-        Game::new(start.role, start.abilities, start.monsters)
+        Game::new(
+            start.role,
+            start.abilities,
+            start.monsters,
+            start.health,
+            start.piles,
+        )
     }
 }
 
@@ -371,17 +488,19 @@ impl IncomingSocketMessage for Game {
 
 use tokio::net::tcp::{ReadHalf, WriteHalf};
 pub struct ClientIO<'a> {
-    writer: FramedWrite<WriteHalf<'a>, LinesCodec>,
-    reader: MessageDecoder<FramedRead<ReadHalf<'a>, LinesCodec>>,
+    writer: FramedWrite<WriteHalf<'a>, FrameCodec>,
+    reader: MessageDecoder<FramedRead<ReadHalf<'a>, FrameCodec>>,
     input: crossterm::event::EventStream,
     terminal: Arc<Mutex<TerminalHandle>>,
+    // a last word of the server for the restored terminal
+    farewell: Option<String>,
 }
 
 pub trait DataForNextState {
     type Type;
 }
 impl DataForNextState for Intro {
-    type Type = GameContext<(), (), Option<Role>, StartGame>;
+    type Type = GameContext<(), server::RoomCode, Option<Role>, StartGame>;
 }
 impl DataForNextState for Home {
     type Type = Option<Role>;
@@ -432,6 +551,13 @@ impl MessageReceiver<server::IntroMsg, &mut Connection<Intro>> for Context<Intro
                             .tx
                             .send(Msg::with(client::IntroMsg::GetChatLog))
                             .expect("failed to request a chat log");
+                        state
+                            .tx
+                            .send(Msg::with(match self.state.room {
+                                Some(code) => client::IntroMsg::JoinRoom(code),
+                                None => client::IntroMsg::GetRooms,
+                            }))
+                            .expect("failed to request rooms");
                         Ok(())
                     }
                     LoginStatus::Reconnected => {
@@ -448,17 +574,47 @@ impl MessageReceiver<server::IntroMsg, &mut Connection<Intro>> for Context<Intro
                     LoginStatus::AlreadyLogged => {
                         Err(anyhow!("User with name '{}' already logged", self.username))
                     }
+                    LoginStatus::BadSession => Err(anyhow!(
+                        "'{}' plays a started game from another session",
+                        self.username
+                    )),
+                    // ask the password again
+                    LoginStatus::BadCredentials | LoginStatus::AccountExists => {
+                        self.state.password.reset();
+                        Ok(())
+                    }
                 }
                 .context("Failed to join to the game");
             }
-            IntroMsg::StartHome => {
+            IntroMsg::Session(token) => {
+                self.state.session.save(&token);
+            }
+            IntroMsg::StartHome(room) => {
                 state
                     .cancel
                     .take()
                     .unwrap()
-                    .send(Some(GameContext::Home(())))
+                    .send(Some(GameContext::Home(room)))
                     .map_err(|_| anyhow!("Failed done"))?;
             }
+            IntroMsg::Rooms(rooms) => {
+                let active = self.state.rooms.active.unwrap_or_default();
+                self.state.rooms = StatefulList::with_items(rooms);
+                self.state.rooms.active = match self.state.rooms.items.len() {
+                    0 => None,
+                    len => Some(active.min(len - 1)),
+                };
+            }
+            IntroMsg::Watch(view) => {
+                self.state.watching = Some(view);
+            }
+            IntroMsg::RoomRejected(e) => {
+                self.state.rejected = Some(e);
+                state
+                    .tx
+                    .send(Msg::with(client::IntroMsg::GetRooms))
+                    .expect("failed to request rooms");
+            }
             IntroMsg::ReconnectRoles(role) => {
                 state
                     .cancel
@@ -488,6 +644,22 @@ impl MessageReceiver<server::HomeMsg, &mut Connection<Home>> for Context<Home> {
     fn reduce(&mut self, msg: server::HomeMsg, state: &mut Connection<Home>) -> anyhow::Result<()> {
         use server::HomeMsg;
         match msg {
+            HomeMsg::PlayerCount(count) => {
+                self.state.player_count = count;
+                game_event!(self."Players in the game: {}", count);
+            }
+            HomeMsg::Roster(roster) => {
+                let active = self.state.roster.active.unwrap_or_default();
+                self.state.roster = StatefulList::with_items(roster.players);
+                self.state.roster.active = match self.state.roster.items.len() {
+                    0 => None,
+                    len => Some(active.min(len - 1)),
+                };
+                self.state.locked = roster.locked;
+            }
+            HomeMsg::StartRejected(reason) => {
+                game_event!(self."Can't start: {}", reason);
+            }
             HomeMsg::StartRoles(role) => {
                 state
                     .cancel
@@ -606,44 +778,88 @@ impl MessageReceiver<server::GameMsg, &mut Connection<Game>> for Context<Game> {
             }
 
             GameMsg::Attack(turn) => {
-                turn!(self, turn => |monster| {
-                    self.state.monsters.selected = Some(
-                        self.state.monsters.items
-                        .iter()
-                        .position(|i| i.is_some_and(|i| i == monster))
-                        .expect("Must exists"));
-                    game_event!(self."You attack {:?}", self.state.monsters.active().unwrap());
-                    self.state.abilities.selected = None;
+                turn!(self, turn => |attack| {
+                    match attack {
+                        Ok(monster) => {
+                            self.state.monsters.selected = Some(
+                                self.state.monsters.items
+                                .iter()
+                                .position(|i| i.is_some_and(|i| i == monster))
+                                .expect("Must exists"));
+                            game_event!(self."You attack {:?}", self.state.monsters.active().unwrap());
+                            self.state.abilities.selected = None;
+                        }
+                        Err(server::AttackError::TooWeak{ability, monster}) => {
+                            game_event!(self."Your {:?} is too weak against {:?}", ability, monster);
+                        }
+                        Err(server::AttackError::NothingToSwap{monster}) => {
+                            game_event!(self."{:?} has no unseen monster of its kind to swap with", monster);
+                        }
+                        Err(server::AttackError::NoTarget{ability}) => {
+                            game_event!(self."No monster falls to your {:?}, it is discarded", ability);
+                            self.state.abilities.selected = None;
+                        }
+                    }
                     self.state.monsters.selected  = None;
                 });
             }
             GameMsg::Defend(monster) => {
                 match monster {
                     Some(m) => {
-                        self.state.attack_monster = Some(
-                            self.state
-                                .monsters
-                                .items
-                                .iter()
-                                .position(|i| i.is_some_and(|i| i == m))
-                                .expect("Must be Some"),
-                        );
-                        game_event!(self."A {:?} Attack You!", 
-                                        self.state.monsters.items[self.state.attack_monster
-                                                            .expect("Must attack")]
-                                                            .expect("Must be Some"));
-                        game_event!(self."You get damage from {:?}", self.state.monsters.items[
-                            self.state.attack_monster.expect("Must attack")]);
+                        self.state.attack_monster = self
+                            .state
+                            .monsters
+                            .items
+                            .iter()
+                            .position(|i| i.is_some_and(|i| i == m));
+                        if self.state.attack_monster.is_none() {
+                            // the line is not synced yet, the next update redraws it
+                            warn!("The attacking {:?} is not on the monster line", m);
+                        }
+                        game_event!(self."A {:?} Attack You!", m);
                     }
                     None => {
+                        self.state.attack_monster = None;
                         game_event!(self."You defend");
                     }
                 };
                 self.state.monsters.selected = None;
             }
-            GameMsg::UpdateGameData((monsters, abilities)) => {
-                self.state.monsters.items = monsters;
-                self.state.abilities.items = abilities;
+            GameMsg::MoveRejected(reason) => {
+                use server::MoveRejection;
+                match reason {
+                    MoveRejection::GameOver => game_event!(self."The game is over"),
+                    MoveRejection::WrongPhase(phase) => {
+                        game_event!(self."This move is not allowed in the {:?} phase", phase)
+                    }
+                    MoveRejection::NotInHand(ability) => {
+                        game_event!(self."{:?} is not in your hand", ability)
+                    }
+                    MoveRejection::NoSelectedAbility => {
+                        game_event!(self."Select an ability before the attack")
+                    }
+                    MoveRejection::NotOnMonsterLine(monster) => {
+                        game_event!(self."{:?} is not on the monster line", monster)
+                    }
+                }
+            }
+            GameMsg::UpdateGameData(data) => {
+                self.state.monsters.items = data.monsters;
+                self.state.abilities.items = data.abilities;
+                self.state.health = data.health;
+                self.state.piles = data.piles;
+            }
+            GameMsg::GameOver { outcome, stats } => {
+                self.state.phase = TurnStatus::Wait;
+                self.state.game_over = Some((outcome, stats));
+                match outcome {
+                    server::GameOutcome::Victory => {
+                        game_event!(self."Victory! All monsters are defeated")
+                    }
+                    server::GameOutcome::Defeat => {
+                        game_event!(self."Defeat! All players are fallen")
+                    }
+                }
             }
         }
 
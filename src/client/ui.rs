@@ -16,7 +16,10 @@ use ratatui::{
 use tracing::{debug, error};
 
 use super::{input::InputMode, states::Chat};
-use crate::protocol::server::ChatLine;
+use crate::protocol::{
+    server::{ChatLine, LoginStatus},
+    GameContextKind,
+};
 
 pub mod details;
 pub mod game;
@@ -102,6 +105,12 @@ use super::super::client::states::{Context, Intro};
 
 impl Drawable for Context<Intro> {
     fn draw(&mut self, f: &mut Frame<Backend>, area: Rect) {
+        if !self.state.is_logged() {
+            return self.draw_login(f, area);
+        }
+        if self.state.watching.is_some() {
+            return self.draw_watch(f, area);
+        }
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints(
@@ -120,23 +129,188 @@ impl Drawable for Context<Intro> {
             .wrap(Wrap { trim: true });
 
         f.render_widget(intro, chunks[0]);
-        let title = Paragraph::new("Intro label here")
-            .style(Style::default().add_modifier(Modifier::BOLD))
-            .alignment(Alignment::Center);
-        f.render_widget(title, chunks[1]);
 
-        use super::input::MAIN_KEYS;
+        use super::ui::details::Statefulness;
+        let active = self.state.rooms.active().map(|r| r.code);
+        let mut rooms = self
+            .state
+            .rooms
+            .items
+            .iter()
+            .map(|r| {
+                Line::from(Span::styled(
+                    format!(
+                        "{}  {:<20} {:?} {}/{}",
+                        r.code, r.host, r.state, r.players, r.player_count
+                    ),
+                    if active == Some(r.code) {
+                        Style::default()
+                            .fg(Color::Cyan)
+                            .add_modifier(Modifier::BOLD)
+                    } else if r.state != GameContextKind::Home {
+                        Style::default().fg(Color::DarkGray)
+                    } else {
+                        Style::default()
+                    },
+                ))
+            })
+            .collect::<Vec<_>>();
+        if rooms.is_empty() {
+            rooms.push(Line::from("No open rooms yet"));
+        }
+        if let Some(e) = self.state.rejected {
+            rooms.insert(
+                0,
+                Line::from(Span::styled(
+                    format!("Failed to join the room: {:?}", e),
+                    Style::default().fg(Color::Red),
+                )),
+            );
+        }
+        f.render_widget(
+            Paragraph::new(rooms)
+                .block(Block::default().borders(Borders::ALL).title("Rooms"))
+                .alignment(Alignment::Left),
+            chunks[1],
+        );
+
+        use super::input::{IntroCmd, INTRO_KEYS, MAIN_KEYS};
         KeyHelp(
-            MAIN_KEYS
+            INTRO_KEYS
                 .iter()
-                .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1))),
+                .filter(|a| a.1 != IntroCmd::StopWatching)
+                .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1)))
+                .chain(
+                    MAIN_KEYS
+                        .iter()
+                        .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1))),
+                ),
         )
         .draw(f, chunks[2]);
     }
 }
-struct DisplayIntroAction<'a>(&'a KeyEvent, MainCmd);
-impl<'a> From<DisplayIntroAction<'a>> for Vec<Span<'a>> {
-    fn from(value: DisplayIntroAction) -> Self {
+impl Context<Intro> {
+    fn draw_login(&mut self, f: &mut Frame<Backend>, area: Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Percentage(50),
+                    Constraint::Length(3),
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+        let input = Paragraph::new("*".repeat(self.state.password.value().chars().count())).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Password of {}", self.username)),
+        );
+        f.render_widget(input, chunks[1]);
+        f.set_cursor(
+            chunks[1].x + self.state.password.visual_cursor() as u16 + 1,
+            chunks[1].y + 1,
+        );
+        if let Some(status) = self.state.status {
+            f.render_widget(
+                Paragraph::new(Span::styled(
+                    match status {
+                        LoginStatus::AccountExists => "This username is already registered",
+                        _ => "Unknown username or wrong password",
+                    },
+                    Style::default().fg(Color::Red),
+                )),
+                chunks[2],
+            );
+        }
+
+        use super::input::{LOGIN_KEYS, MAIN_KEYS};
+        KeyHelp(
+            LOGIN_KEYS
+                .iter()
+                .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1)))
+                .chain(
+                    MAIN_KEYS
+                        .iter()
+                        .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1))),
+                ),
+        )
+        .draw(f, chunks[3]);
+    }
+    fn draw_watch(&mut self, f: &mut Frame<Backend>, area: Rect) {
+        let view = self.state.watching.as_ref().expect("Watching a game");
+        let main_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(99), Constraint::Length(1)].as_ref())
+            .split(area);
+        let screen_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+            .split(main_layout[0]);
+        let mut lines = vec![
+            Line::from(match view.outcome {
+                Some(outcome) => format!("Game over: {:?}", outcome),
+                None => format!("Turn of {} ({:?})", view.active_player, view.phase),
+            }),
+            Line::from(""),
+            Line::from(format!(
+                "Monsters: {}",
+                view.monsters
+                    .iter()
+                    .map(|m| m.map_or("-".to_string(), |m| format!("{:?}", m)))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            )),
+            Line::from(""),
+        ];
+        lines.extend(view.players.iter().map(|p| {
+            Line::from(Span::styled(
+                format!(
+                    "{:<20} {:<8} {}/{}",
+                    p.name,
+                    format!("{:?}", p.role),
+                    p.health,
+                    crate::protocol::server::MAX_HEALTH
+                ),
+                if p.health == 0 {
+                    Style::default().fg(Color::DarkGray)
+                } else if p.name == view.active_player {
+                    Style::default()
+                        .fg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                },
+            ))
+        }));
+        f.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title("Spectator"))
+                .wrap(Wrap { trim: true }),
+            screen_chunks[0],
+        );
+        self.chat.draw(f, screen_chunks[1]);
+
+        use super::input::{IntroCmd, INTRO_KEYS, MAIN_KEYS};
+        KeyHelp(
+            INTRO_KEYS
+                .iter()
+                .filter(|a| a.1 == IntroCmd::StopWatching)
+                .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1)))
+                .chain(
+                    MAIN_KEYS
+                        .iter()
+                        .flat_map(|a| Vec::<Span<'_>>::from(DisplayIntroAction(&a.0, a.1))),
+                ),
+        )
+        .draw(f, main_layout[1]);
+    }
+}
+struct DisplayIntroAction<'a, A: TryInto<&'static str>>(&'a KeyEvent, A);
+impl<'a, A: TryInto<&'static str>> From<DisplayIntroAction<'a, A>> for Vec<Span<'a>> {
+    fn from(value: DisplayIntroAction<'a, A>) -> Self {
         value
             .1
             .try_into()
@@ -321,12 +495,33 @@ macro_rules! str_try_from_context_cmd {
     (@read_name $cmd:ident =>) => (stringify!($cmd));
 }
 
-use super::input::{ChatCmd, HomeCmd, MainCmd, RolesCmd};
+use super::input::{ChatCmd, HomeCmd, IntroCmd, LoginCmd, MainCmd, RolesCmd};
 str_try_from_context_cmd! { MainCmd {
     Quit ,
 }}
+str_try_from_context_cmd! { LoginCmd {
+    Login "log in",
+    Register "register",
+}}
+str_try_from_context_cmd! { IntroCmd {
+    SelectPrev ,
+    SelectNext ,
+    JoinRoom "join",
+    CreateRoom "create a room",
+    WatchRoom "watch",
+    StopWatching "stop watching",
+    RefreshRooms "refresh",
+}}
 str_try_from_context_cmd! { HomeCmd {
     EnterChat ,
+    FewerPlayers ,
+    MorePlayers ,
+    AddBot "add a bot",
+    SelectPrev ,
+    SelectNext ,
+    Ready "ready",
+    Kick "kick",
+    Lock "lock",
     StartRoles "StartGame",
 
 }}
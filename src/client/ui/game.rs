@@ -14,7 +14,7 @@ use super::{Backend, Drawable};
 use crate::{
     client,
     game::{Card, Rank, Suit},
-    protocol::{GamePhaseKind, TurnStatus},
+    protocol::{server, server::MAX_HEALTH, GamePhaseKind, TurnStatus},
 };
 
 const CARD_WIDTH: u16 = 45 + 1;
@@ -79,8 +79,12 @@ impl Drawable for Context<Game> {
             .split(screen_layout[2]);
 
         self.chat.draw(f, chat_layout[0]);
-        // TODO username
-        Hud::new("Ig", (self.state.health, 36)).draw(f, chat_layout[1]);
+        Hud::new(
+            &self.username,
+            (self.state.health, MAX_HEALTH),
+            self.state.piles,
+        )
+        .draw(f, chat_layout[1]);
 
         Abilities(self.state.role, &self.state.abilities, self.state.phase)
             .draw(f, viewport_layout[1]);
@@ -90,6 +94,9 @@ impl Drawable for Context<Game> {
             self.state.attack_monster,
         )
         .draw(f, viewport_layout[0]);
+        if let Some((outcome, stats)) = self.state.game_over {
+            GameOver(outcome, stats).draw(f, main_layout[0]);
+        }
     }
 }
 
@@ -125,10 +132,15 @@ impl TryFrom<DisplayGameAction> for &'static str {
 struct Hud<'a> {
     username: &'a str,
     health: (u16, u16),
+    piles: server::PileSizes,
 }
 impl<'a> Hud<'a> {
-    fn new(username: &'a str, health: (u16, u16)) -> Self {
-        Hud { username, health }
+    fn new(username: &'a str, health: (u16, u16), piles: server::PileSizes) -> Self {
+        Hud {
+            username,
+            health,
+            piles,
+        }
     }
 }
 impl<'a> Drawable for Hud<'a> {
@@ -152,7 +164,7 @@ impl<'a> Drawable for Hud<'a> {
         let gauge = Gauge::default()
             .block(Block::default()) //.title("Health").borders(Borders::NONE))
             .gauge_style(Style::default().bg(Color::DarkGray)) //.add_modifier(Modifier::REVERSED))//.bg(Color::Cyan))
-            .percent(self.health.0 / 100 * self.health.1)
+            .percent(self.health.0 * 100 / self.health.1)
             .label(Span::styled(
                 format!("🤍 {}/{}", self.health.0, self.health.1),
                 Style::default()
@@ -165,6 +177,45 @@ impl<'a> Drawable for Hud<'a> {
                 .padding(Padding::new(4, 4, 1, 1))
                 .inner(layout[1]),
         );
+        f.render_widget(
+            Paragraph::new(format!(
+                "Draw pile {} | Discard pile {}",
+                self.piles.draw, self.piles.discard
+            ))
+            .alignment(Alignment::Center),
+            layout[2],
+        );
+    }
+}
+
+struct GameOver(server::GameOutcome, server::GameStats);
+impl Drawable for GameOver {
+    fn draw(&mut self, f: &mut Frame<Backend>, area: Rect) {
+        const RESULT_WIDTH: u16 = 40;
+        const RESULT_HEIGHT: u16 = 9;
+        let pad_v = area.height.saturating_sub(RESULT_HEIGHT).saturating_div(2);
+        let pad_h = area.width.saturating_sub(RESULT_WIDTH).saturating_div(2);
+        let area = Block::default()
+            .padding(Padding::new(pad_h, pad_h, pad_v, pad_v))
+            .inner(area);
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(format!(
+                "\nRounds: {}\nMonsters defeated: {}\nMonsters left: {}\n\nPress Ctrl+q to quit",
+                self.1.rounds, self.1.monsters_defeated, self.1.monsters_left
+            ))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(match self.0 {
+                        server::GameOutcome::Victory => "Victory!",
+                        server::GameOutcome::Defeat => "Defeat",
+                    })
+                    .title_alignment(Alignment::Center),
+            )
+            .alignment(Alignment::Center),
+            area,
+        );
     }
 }
 
@@ -220,16 +271,17 @@ impl<'a> Drawable for Monster<'a> {
     }
 }
 struct Monsters<'a>(
-    &'a StatefulList<Option<Card>, [Option<Card>; 2]>,
+    &'a StatefulList<Option<Card>, server::MonsterLine>,
     TurnStatus,
     Option<usize>, /*attack monster*/
 );
 
 impl<'a> Drawable for Monsters<'a> {
     fn draw(&mut self, f: &mut Frame<Backend>, area: Rect) {
+        let count = self.0.items.len() as u32;
         let layout = Layout::default()
             .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .constraints(vec![Constraint::Ratio(1, count); count as usize])
             .split(area);
 
         for (i, card) in self.0.items.iter().enumerate() {
@@ -495,7 +547,9 @@ mod tests {
         let cards = [
             Some(Card::new(Rank::Queen, Suit::Diamonds)),
             Some(Card::new(Rank::Eight, Suit::Diamonds)),
-        ];
+        ]
+        .into_iter()
+        .collect();
         let chat = Chat {
             input_mode: InputMode::Editing,
             ..Default::default()
@@ -507,6 +561,8 @@ mod tests {
                 Suit::Clubs,
                 [Some(Rank::Six), Some(Rank::Seven), Some(Rank::Eight)],
                 cards,
+                MAX_HEALTH,
+                Default::default(),
             ),
         };
         game.state.phase = TurnStatus::Ready(GamePhaseKind::DropAbility);
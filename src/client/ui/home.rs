@@ -46,7 +46,50 @@ impl Drawable for Context<Home> {
             .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
             .split(main_layout[0]);
 
-        let viewport = Paragraph::new("village here").block(Block::default().borders(Borders::ALL));
+        use super::details::Statefulness;
+        let roster = &self.state.roster.items;
+        let mut lines = vec![
+            Line::from("village here"),
+            Line::from(""),
+            Line::from(format!("Room code: {}", self.state.room)),
+            Line::from(format!(
+                "Players: {}/{}",
+                roster.len(),
+                self.state.player_count
+            )),
+        ];
+        let waiting = (self.state.player_count as usize).saturating_sub(roster.len());
+        if waiting > 0 {
+            lines.push(Line::from(format!("Waiting for {} more players", waiting)));
+        }
+        if self.state.locked {
+            lines.push(Line::from(Span::styled(
+                "The room is locked",
+                Style::default().fg(Color::Yellow),
+            )));
+        }
+        lines.push(Line::from(""));
+        let active = self.state.roster.active().map(|p| &p.name);
+        lines.extend(roster.iter().enumerate().map(|(i, p)| {
+            Line::from(Span::styled(
+                format!(
+                    "{} {:<20}{}",
+                    if p.ready { "[x]" } else { "[ ]" },
+                    p.name,
+                    if i == 0 { " host" } else { "" }
+                ),
+                if active == Some(&p.name) {
+                    Style::default()
+                        .fg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD)
+                } else if p.ready {
+                    Style::default().fg(Color::Green)
+                } else {
+                    Style::default()
+                },
+            ))
+        }));
+        let viewport = Paragraph::new(lines).block(Block::default().borders(Borders::ALL));
 
         if false {
             let viewport_chunks = Layout::default()
@@ -1,8 +1,13 @@
 use arrayvec::ArrayVec;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    thread_rng,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::details::create_enum_iter;
+use crate::{details::create_enum_iter, protocol::server::ABILITY_COUNT};
+
+pub mod effects;
 
 create_enum_iter! {
     #[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize, Eq)]
@@ -14,18 +19,65 @@ create_enum_iter! {
     }
 }
 
+// a part of each monster hit absorbed by the Warrior armor
+pub const WARRIOR_ARMOR: u16 = 2;
+// extra ranks of Rogue abilities against monsters of the Rogue suit
+pub const ROGUE_SUIT_BONUS: u16 = 2;
+
 impl Role {
     pub fn description(&self) -> &'static str {
+        use const_format::formatcp;
+        match self {
+            Role::Warrior => formatcp!(
+                "Passive. Armor: every monster hit deals {} less damage to the Warrior.",
+                WARRIOR_ARMOR
+            ),
+            Role::Rogue => formatcp!(
+                "Passive. Backstab: abilities are {} ranks stronger \
+                 against monsters of the Rogue suit \u{2662}.",
+                ROGUE_SUIT_BONUS
+            ),
+            Role::Paladin => {
+                "Active. Shield: a dropped ability shields the next teammate \
+                 attacked by a monster. The shield absorbs damage equal to the ability value."
+            }
+            Role::Mage => {
+                "Active. Chain lightning: an attack with an Ace \
+                 hits every monster on the line."
+            }
+        }
+    }
+    // damage that reaches a player of this role
+    pub fn take_damage(self, damage: u16) -> u16 {
         match self {
-            Role::Warrior => include_str!("./assets/roles/warrior/description.txt"),
-            Role::Rogue => include_str!("./assets/roles/rogue/description.txt"),
-            Role::Paladin => include_str!("./assets/roles/paladin/description.txt"),
-            Role::Mage => include_str!("./assets/roles/mage/description.txt"),
+            Role::Warrior => damage.saturating_sub(WARRIOR_ARMOR),
+            _ => damage,
         }
     }
+    // an ability defeats a monster of the same or a lower rank
+    pub fn can_defeat(self, ability: Rank, monster: &Card) -> bool {
+        let bonus = match self {
+            Role::Rogue if monster.suit == Suit::from(self) => ROGUE_SUIT_BONUS,
+            _ => 0,
+        };
+        ability as u16 + bonus >= monster.rank as u16
+    }
+    pub fn shields_on_drop(self) -> bool {
+        self == Role::Paladin
+    }
+    pub fn hits_whole_line(self, ability: Rank) -> bool {
+        self == Role::Mage && ability == Rank::Ace
+    }
+    // a miss keeps the turn only while some monster falls to the ability
+    pub fn has_target(self, ability: Rank, monsters: &[Option<Card>]) -> bool {
+        monsters
+            .iter()
+            .flatten()
+            .any(|m| self.can_defeat(ability, m))
+    }
 }
 create_enum_iter! {
-    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum Rank {
         Six   = 5,
         Seven = 6,
@@ -38,6 +90,11 @@ create_enum_iter! {
         Ace   = 13,
     }
 }
+impl Rank {
+    pub fn value(self) -> u16 {
+        self as u16 + 1
+    }
+}
 impl From<Rank> for String {
     fn from(rank: Rank) -> Self {
         use Rank::*;
@@ -52,7 +109,7 @@ impl From<Rank> for String {
 }
 
 create_enum_iter! {
-    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum Suit {
         Hearts = 0,
         Diamonds =1,
@@ -80,13 +137,34 @@ impl Card {
     pub fn new(rank: Rank, suit: Suit) -> Self {
         Card { rank, suit }
     }
+    // a monster hits by the value of its card
+    pub fn damage(&self) -> u16 {
+        self.rank.value()
+    }
+    // court cards are bosses of the monster deck
+    pub fn is_boss(&self) -> bool {
+        matches!(self.rank, Rank::Jack | Rank::Queen | Rank::King)
+    }
+    // successful attacks to defeat a monster.
+    // a Jack and a Queen need two hits, a King needs three
+    pub fn hits(&self) -> u8 {
+        match self.rank {
+            Rank::Jack | Rank::Queen => 2,
+            Rank::King => 3,
+            _ => 1,
+        }
+    }
+    // a wounded Queen recovers before the next round
+    pub fn regenerates(&self) -> bool {
+        self.rank == Rank::Queen
+    }
 }
 pub trait Deckable {
     const DECK_SIZE: usize = Rank::all().len() * Suit::all().len();
     fn shuffle(&mut self);
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Deck {
     pub cards: ArrayVec<Card, { Deck::DECK_SIZE }>,
 }
@@ -97,6 +175,16 @@ impl Deck {
             cards: Default::default(),
         }
     }
+    // an unseen card of the same kind takes the place of the card at `i`,
+    // so bosses stay every third card and in the order of ranks
+    pub fn swap_with_unseen(&mut self, i: usize, unseen: usize) -> Option<Card> {
+        let kind = |c: &Card| c.is_boss().then_some(c.rank);
+        let other = (unseen..self.cards.len())
+            .filter(|j| kind(&self.cards[*j]) == kind(&self.cards[i]))
+            .choose(&mut thread_rng())?;
+        self.cards.swap(i, other);
+        Some(self.cards[i])
+    }
 }
 impl Deckable for Deck {
     fn shuffle(&mut self) {
@@ -123,31 +211,72 @@ impl_from! {
                 Mage    => Spades,
     }
 }
+impl_from! {
+    impl From ( )  Suit for Role {
+                Hearts   => Warrior,
+                Diamonds => Rogue,
+                Clubs    => Paladin,
+                Spades   => Mage,
+    }
+}
+
+// each reshuffle of the discard pile costs health
+pub const EXHAUSTION_DAMAGE: u16 = 2;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Ability {0:?} is not in the hand")]
+pub struct NotInHand(pub Rank);
+
+// abilities of a player are split between the hand, the draw and the discard piles
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AbilityDeck {
-    pub ranks: ArrayVec<Rank, { Rank::all().len() }>,
     pub suit: Suit,
+    pub hand: [Option<Rank>; ABILITY_COUNT],
+    pub draw_pile: ArrayVec<Rank, { Rank::all().len() }>,
+    pub discard_pile: ArrayVec<Rank, { Rank::all().len() }>,
 }
 
 impl AbilityDeck {
     pub fn new(suit: Suit) -> Self {
         AbilityDeck {
             suit,
-            ranks: (*Rank::all()).into(),
+            hand: Default::default(),
+            draw_pile: (*Rank::all()).into(),
+            discard_pile: Default::default(),
         }
     }
-    #[allow(dead_code)]
-    fn empty(suit: Suit) -> Self {
-        AbilityDeck {
-            suit,
-            ranks: Default::default(),
+    // fill empty slots of the hand from the draw pile.
+    // An empty draw pile takes the shuffled discard pile
+    // with the exhaustion damage as a penalty
+    pub fn draw(&mut self) -> u16 {
+        let mut penalty = 0;
+        for slot in self.hand.iter_mut().filter(|s| s.is_none()) {
+            if self.draw_pile.is_empty() && !self.discard_pile.is_empty() {
+                self.draw_pile.extend(self.discard_pile.drain(..));
+                self.draw_pile.shuffle(&mut thread_rng());
+                penalty += EXHAUSTION_DAMAGE;
+            }
+            *slot = self.draw_pile.pop();
         }
+        penalty
+    }
+    // dropped and used abilities go to the discard pile
+    pub fn discard(&mut self, ability: Rank) -> Result<(), NotInHand> {
+        *self
+            .hand
+            .iter_mut()
+            .find(|a| **a == Some(ability))
+            .ok_or(NotInHand(ability))? = None;
+        self.discard_pile.push(ability);
+        Ok(())
+    }
+    pub fn in_hand(&self, ability: Rank) -> bool {
+        self.hand.contains(&Some(ability))
     }
 }
 impl Deckable for AbilityDeck {
     fn shuffle(&mut self) {
-        self.ranks.shuffle(&mut thread_rng());
+        self.draw_pile.shuffle(&mut thread_rng());
     }
 }
 
@@ -162,9 +291,9 @@ impl MonsterDeck for Deck {
             .map(|c| Suit::all().map(|suit| Card { suit, rank: c }));
         bosses.iter_mut().for_each(|b| b.shuffle(&mut rng));
 
-        let mut card_iter = Rank::all()[..Rank::Ten as usize]
-            .iter()
-            .flat_map(|r| Suit::iter().map(|s| Card { suit: s, rank: *r }));
+        let mut card_iter = Rank::iter()
+            .flat_map(|r| Suit::iter().map(move |s| Card { suit: s, rank: r }))
+            .filter(|c| !c.is_boss());
         let mut other_cards: [Card;
             (Rank::Ten as usize - Rank::Six as usize + 1 + 1) * Suit::all().len()] =
             core::array::from_fn(|_| card_iter.next().unwrap());
@@ -207,4 +336,118 @@ mod tests {
             .enumerate()
             .for_each(|(i, m)| println!("{i}: {:?}", m));
     }
+    #[test]
+    fn every_third_monster_is_a_boss() {
+        let deck = Deck::new_monster_deck();
+        assert_eq!(deck.cards.len(), Deck::DECK_SIZE);
+        deck.cards
+            .iter()
+            .enumerate()
+            .for_each(|(i, m)| assert_eq!(m.is_boss(), i % 3 == 0, "{i}: {:?}", m));
+    }
+    #[test]
+    fn bosses_come_in_king_queen_jack_order() {
+        let deck = Deck::new_monster_deck();
+        deck.cards
+            .iter()
+            .step_by(3)
+            .zip([Rank::King, Rank::Queen, Rank::Jack].iter().cycle())
+            .for_each(|(m, r)| assert_eq!(m.rank, *r));
+    }
+    #[test]
+    fn monster_deck_contains_each_card_once() {
+        let deck = Deck::new_monster_deck();
+        Deck::default()
+            .cards
+            .iter()
+            .for_each(|c| assert_eq!(deck.cards.iter().filter(|m| *m == c).count(), 1));
+    }
+    #[test]
+    fn bosses_need_more_hits() {
+        assert_eq!(Card::new(Rank::Ace, Suit::Hearts).hits(), 1);
+        assert_eq!(Card::new(Rank::Jack, Suit::Clubs).hits(), 2);
+        assert_eq!(Card::new(Rank::Queen, Suit::Spades).hits(), 2);
+        assert_eq!(Card::new(Rank::King, Suit::Diamonds).hits(), 3);
+    }
+    #[test]
+    fn ability_defeats_monster_of_same_or_lower_rank() {
+        let role = Role::Warrior;
+        assert!(role.can_defeat(Rank::Six, &Card::new(Rank::Six, Suit::Clubs)));
+        assert!(role.can_defeat(Rank::Ace, &Card::new(Rank::King, Suit::Clubs)));
+        assert!(!role.can_defeat(Rank::Ten, &Card::new(Rank::Jack, Suit::Clubs)));
+        assert!(Deck::default()
+            .cards
+            .iter()
+            .all(|m| role.can_defeat(Rank::Ace, m)));
+    }
+    #[test]
+    fn discarded_abilities_return_after_exhaustion() {
+        let mut deck = AbilityDeck::new(Suit::Hearts);
+        deck.shuffle();
+        assert_eq!(deck.draw(), 0);
+        let mut penalty = 0;
+        for _ in 0..Rank::all().len() {
+            let ability = deck.hand[0].expect("Hand is full");
+            deck.discard(ability).unwrap();
+            assert!(deck.discard(ability).is_err());
+            penalty += deck.draw();
+        }
+        assert_eq!(penalty, EXHAUSTION_DAMAGE);
+        assert!(deck.hand.iter().all(Option::is_some));
+        assert_eq!(
+            deck.hand.len() + deck.draw_pile.len() + deck.discard_pile.len(),
+            Rank::all().len()
+        );
+    }
+    #[test]
+    fn role_mechanics() {
+        assert_eq!(Role::Warrior.take_damage(10), 10 - WARRIOR_ARMOR);
+        assert_eq!(Role::Warrior.take_damage(1), 0);
+        assert_eq!(Role::Mage.take_damage(10), 10);
+        let jack = |suit| Card::new(Rank::Jack, suit);
+        assert!(Role::Rogue.can_defeat(Rank::Nine, &jack(Suit::Diamonds)));
+        assert!(!Role::Rogue.can_defeat(Rank::Nine, &jack(Suit::Spades)));
+        // the Mage has no bonus of the suit, only the Ace for the whole line
+        assert!(!Role::Mage.can_defeat(Rank::Nine, &jack(Suit::Spades)));
+        assert!(Role::Mage.hits_whole_line(Rank::Ace));
+        assert!(!Role::Mage.hits_whole_line(Rank::King));
+        assert!(!Role::Warrior.hits_whole_line(Rank::Ace));
+        Role::iter().for_each(|r| assert_eq!(Role::from(Suit::from(r)), r));
+    }
+    #[test]
+    fn swap_monsters_of_the_same_kind() {
+        let mut deck = Deck::new_monster_deck();
+        for (i, unseen) in [(0, 1), (1, 2), (3, 4)] {
+            let monster = deck.cards[i];
+            let new = deck.swap_with_unseen(i, unseen).expect("Unseen cards");
+            assert_ne!(new, monster);
+            assert!(deck.cards[unseen..].contains(&monster));
+        }
+        deck.cards
+            .iter()
+            .enumerate()
+            .for_each(|(i, m)| assert_eq!(m.is_boss(), i % 3 == 0, "{i}: {:?}", m));
+        deck.cards
+            .iter()
+            .step_by(3)
+            .zip([Rank::King, Rank::Queen, Rank::Jack].iter().cycle())
+            .for_each(|(m, r)| assert_eq!(m.rank, *r));
+        // the last king has no unseen king to swap with
+        let last_king = Deck::DECK_SIZE - 9;
+        assert_eq!(deck.cards[last_king].rank, Rank::King);
+        assert!(deck.swap_with_unseen(last_king, last_king + 1).is_none());
+    }
+    #[test]
+    fn miss_without_target_loses_the_turn() {
+        let line = [
+            Some(Card::new(Rank::King, Suit::Clubs)),
+            None,
+            Some(Card::new(Rank::Seven, Suit::Hearts)),
+        ];
+        // too weak for the king, the seven is left
+        assert!(!Role::Warrior.can_defeat(Rank::Eight, &line[0].unwrap()));
+        assert!(Role::Warrior.has_target(Rank::Eight, &line));
+        assert!(!Role::Warrior.has_target(Rank::Six, &line));
+        assert!(!Role::Warrior.has_target(Rank::Ace, &[None, None]));
+    }
 }
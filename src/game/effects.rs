@@ -0,0 +1,88 @@
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use super::{Rank, Suit};
+
+// effects of ability cards. Designers balance the game by the data file,
+// the default table is built into the binary
+const DEFAULT_EFFECTS: &str = include_str!("../assets/abilities/effects.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Effect {
+    // extra hits to the attacked monster
+    Damage(u8),
+    // restore health on select
+    Heal(u16),
+    // refill the empty ability slots on select
+    Draw,
+    // the next monster attack misses the player
+    SkipMonsterAttack,
+    // a too strong monster goes back to the deck and a new one takes its place
+    SwapMonster,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    rank: Rank,
+    suit: Suit,
+    effects: Vec<Effect>,
+}
+
+#[derive(Debug, Default)]
+pub struct Effects(HashMap<(Rank, Suit), Vec<Effect>>);
+
+impl Effects {
+    pub fn parse(data: &str) -> anyhow::Result<Self> {
+        let entries: Vec<Entry> = serde_json::from_str(data)?;
+        let mut table = HashMap::with_capacity(entries.len());
+        for e in entries {
+            if table.insert((e.rank, e.suit), e.effects).is_some() {
+                anyhow::bail!("Duplicate effects of {:?} {:?}", e.rank, e.suit);
+            }
+        }
+        Ok(Effects(table))
+    }
+    pub fn get(&self, rank: Rank, suit: Suit) -> &[Effect] {
+        self.0.get(&(rank, suit)).map_or(&[], Vec::as_slice)
+    }
+}
+
+static EFFECTS: OnceLock<Effects> = OnceLock::new();
+
+// replace the default table, must be called before the game starts
+pub fn load(file: &Path) -> anyhow::Result<()> {
+    let data = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read the effects file {}", file.display()))?;
+    let effects = Effects::parse(&data)
+        .with_context(|| format!("Invalid effects file {}", file.display()))?;
+    EFFECTS
+        .set(effects)
+        .map_err(|_| anyhow::anyhow!("Effects are already loaded"))
+}
+
+pub fn of(rank: Rank, suit: Suit) -> &'static [Effect] {
+    EFFECTS
+        .get_or_init(|| Effects::parse(DEFAULT_EFFECTS).expect("Valid default effects"))
+        .get(rank, suit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_default_effects() {
+        let effects = Effects::parse(DEFAULT_EFFECTS).unwrap();
+        assert_eq!(effects.get(Rank::Queen, Suit::Hearts), &[Effect::Heal(4)]);
+        assert!(effects.get(Rank::Six, Suit::Hearts).is_empty());
+    }
+    #[test]
+    fn reject_duplicate_cards() {
+        let data = r#"[
+            { "rank": "Ace", "suit": "Hearts", "effects": ["Draw"] },
+            { "rank": "Ace", "suit": "Hearts", "effects": [] }
+        ]"#;
+        assert!(Effects::parse(data).is_err());
+    }
+}
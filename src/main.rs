@@ -4,6 +4,7 @@ use std::{
     net::{IpAddr, SocketAddr},
     path::Path,
     sync::Once,
+    time::Duration,
 };
 
 use anyhow::{self, Context as _};
@@ -30,6 +31,13 @@ mod consts {
         ISSUES = const_format::formatcp!("{}/issues/new", REPOSITORY);
         DEFAULT_TCP_PORT = "8000";
         DEFAULT_LOCALHOST = "127.0.0.1";
+        DEFAULT_ACCOUNTS_FILE = "accounts.json";
+        DEFAULT_SNAPSHOTS_DIR = "snapshots";
+        DEFAULT_CALL_TIMEOUT_MS = "5000";
+        DEFAULT_HEARTBEAT_SECS = "5";
+        DEFAULT_MAX_FRAME_BYTES = "16384";
+        DEFAULT_MAX_CHAT_CHARS = "512";
+        DEFAULT_MAX_CHAT_LINES = "10";
         LOG_ENV_VAR = const_format::concatcp!(
             const_format::map_ascii_case!(const_format::Case::Upper, APPNAME),
             "_LOG"
@@ -91,6 +99,66 @@ pub mod commands {
                 ))
                 .arg(address())
                 .arg(tcp())
+                .arg(
+                    arg!(
+                        -e --effects <FILE> "Load ability card effects from a json file"
+                    )
+                    .required(false),
+                )
+                .arg(
+                    arg!(
+                        -a --accounts <FILE> "Keep user accounts in a json file"
+                    )
+                    .default_value(consts::DEFAULT_ACCOUNTS_FILE)
+                    .required(false),
+                )
+                .arg(
+                    arg!(
+                        -s --snapshots <DIR> "Save running games to a directory and restore them on start"
+                    )
+                    .default_value(consts::DEFAULT_SNAPSHOTS_DIR)
+                    .required(false),
+                )
+                .arg(
+                    arg!(
+                        --heartbeat <SECS> "Ping clients every N seconds and drop those who miss 3 pings"
+                    )
+                    .default_value(consts::DEFAULT_HEARTBEAT_SECS)
+                    .required(false)
+                    .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    arg!(
+                        --"call-timeout" <MS> "Deadline of calls between server actors in milliseconds"
+                    )
+                    .default_value(consts::DEFAULT_CALL_TIMEOUT_MS)
+                    .required(false)
+                    .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    arg!(
+                        --"max-frame" <BYTES> "Skip frames of clients longer than N bytes"
+                    )
+                    .default_value(consts::DEFAULT_MAX_FRAME_BYTES)
+                    .required(false)
+                    .value_parser(clap::value_parser!(u64).range(256..)),
+                )
+                .arg(
+                    arg!(
+                        --"max-chat" <CHARS> "Cut chat messages longer than N chars"
+                    )
+                    .default_value(consts::DEFAULT_MAX_CHAT_CHARS)
+                    .required(false)
+                    .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    arg!(
+                        --"max-chat-lines" <LINES> "Drop chat messages of a client over N per 10 seconds"
+                    )
+                    .default_value(consts::DEFAULT_MAX_CHAT_LINES)
+                    .required(false)
+                    .value_parser(clap::value_parser!(u32).range(1..)),
+                )
         }
     }
     pub struct Client;
@@ -108,6 +176,16 @@ pub mod commands {
                     .required(true)
                     .value_parser(username_parser),
                 )
+                .arg(
+                    arg!(
+                        -r --room <CODE> "Join a game room by the code right after login"
+                    )
+                    .required(false)
+                    .value_parser(clap::value_parser!(u16)),
+                )
+                .arg(arg!(
+                    --json "Speak readable json with the server instead of MessagePack, for debugging"
+                ))
         }
     }
     fn address() -> clap::Arg {
@@ -216,22 +294,67 @@ Project home page {}
     };
     match matches.subcommand() {
         Some((commands::Client::NAME, sub_matches)) => {
-            client::connect(
+            let farewell = client::connect(
                 sub_matches
                     .get_one::<crate::protocol::Username>("name")
                     .expect("Required")
                     .to_owned(),
                 get_addr(sub_matches),
+                sub_matches.get_one::<u16>("room").copied(),
+                if sub_matches.get_flag("json") {
+                    &[protocol::codec::Format::Json]
+                } else {
+                    &protocol::codec::Format::ALL
+                },
             )
             .await
             .context("Error while run a client")?;
             tracing::info!("Quit the game");
+            if let Some(farewell) = farewell {
+                println!("{}", farewell);
+            }
         }
         Some((commands::Server::NAME, sub_matches)) => {
             println!(include_str!("assets/ServerIntro.txt"));
-            server::listen(get_addr(sub_matches), signal::ctrl_c())
-                .await
-                .context("Error while run a game server")?;
+            if let Some(file) = sub_matches.get_one::<String>("effects") {
+                game::effects::load(Path::new(file))?;
+            }
+            server::details::set_call_timeout(Duration::from_millis(
+                *sub_matches.get_one::<u64>("call-timeout").expect("Default"),
+            ))?;
+            let accounts = server::accounts::Accounts::load(
+                sub_matches
+                    .get_one::<String>("accounts")
+                    .expect("Default")
+                    .into(),
+            )?;
+            let snapshots = server::snapshot::Snapshots::new(
+                sub_matches
+                    .get_one::<String>("snapshots")
+                    .expect("Default")
+                    .into(),
+            );
+            server::listen(
+                get_addr(sub_matches),
+                server::states::IntroServer::new(accounts, Some(snapshots)),
+                server::peer::Heartbeat {
+                    interval: Duration::from_secs(
+                        *sub_matches.get_one::<u64>("heartbeat").expect("Default"),
+                    ),
+                    ..Default::default()
+                },
+                server::peer::Limits {
+                    max_frame: *sub_matches.get_one::<u64>("max-frame").expect("Default") as usize,
+                    max_chat: *sub_matches.get_one::<u64>("max-chat").expect("Default") as usize,
+                    max_chat_lines: *sub_matches
+                        .get_one::<u32>("max-chat-lines")
+                        .expect("Default"),
+                    ..Default::default()
+                },
+                signal::ctrl_c(),
+            )
+            .await
+            .context("Error while run a game server")?;
             tracing::info!("Close the server");
         }
         _ => unreachable!("Exhausted list of subcommands.."),
@@ -4,12 +4,15 @@ use arraystring::{typenum::U20, ArrayString};
 use derive_more::{Debug, From};
 use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio_util::codec::{Decoder, LinesCodec};
+use tokio_util::codec::FramedRead;
 
 pub mod client;
+pub mod codec;
 pub mod details;
 pub mod server;
 
+use codec::{BadFrame, Format, Frame, FrameCodec, Outgoing};
+
 use crate::game::Role;
 
 #[repr(transparent)]
@@ -41,6 +44,58 @@ impl Username {
     }
 }
 
+// never shows up in logs
+#[derive(Default, derive_more::Debug, Clone, Deserialize, Serialize, From, derive_more::Deref)]
+#[debug("***")]
+pub struct Password(#[deref(forward)] String);
+
+// an opaque proof of the last login, reclaims a seat of a started game
+#[derive(derive_more::Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[debug("***")]
+pub struct SessionToken(String);
+impl SessionToken {
+    pub fn generate() -> Self {
+        SessionToken(format!("{:032x}", rand::random::<u128>()))
+    }
+}
+
+// bumped on every change of messages that breaks older peers
+pub const PROTOCOL_VERSION: u16 = 1;
+
+// the first message of a connection from both sides, before any Msg.
+// It stays the same across versions, so an outdated peer still learns
+// why it is rejected
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub protocol_version: u16,
+    pub app_version: String,
+    // optional features of the sender, unknown ones are ignored
+    pub capabilities: Vec<String>,
+}
+impl Default for Hello {
+    fn default() -> Self {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            app_version: crate::consts::VERSION.to_string(),
+            capabilities: Format::capabilities(&Format::ALL),
+        }
+    }
+}
+
+// an answer of the server to Hello
+pub type Welcome = Result<Hello, VersionMismatch>;
+
+#[derive(thiserror::Error, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "The server {server_app} speaks the protocol version {server}, but this client \
+     speaks the version {client}. Install the client of version {server_app}"
+)]
+pub struct VersionMismatch {
+    pub server: u16,
+    pub client: u16,
+    pub server_app: String,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub enum Msg<SharedMsg, StateMsg> {
     Shared(SharedMsg),
@@ -212,22 +267,29 @@ pub struct MessageDecoder<S> {
     stream: S,
 }
 
+impl<R> MessageDecoder<FramedRead<R, FrameCodec>> {
+    #[inline]
+    pub fn set_format(&mut self, format: Format) {
+        self.stream.decoder_mut().set_format(format);
+    }
+}
+
 impl<S> MessageDecoder<S>
 where
-    S: Stream<Item = Result<<LinesCodec as Decoder>::Item, <LinesCodec as Decoder>::Error>>
-        + StreamExt
-        + Unpin,
+    S: Stream<Item = Result<Result<Frame, BadFrame>, Error>> + StreamExt + Unpin,
 {
     pub fn new(stream: S) -> Self {
         MessageDecoder { stream }
     }
+    // a malformed or oversized frame is an error of InvalidData,
+    // the next call reads the following frame
     pub async fn next<M>(&mut self) -> Option<Result<M, Error>>
     where
         M: for<'b> serde::Deserialize<'b>,
     {
         match self.stream.next().await {
             Some(msg) => match msg {
-                Ok(msg) => Some(serde_json::from_str::<M>(&msg).map_err(|err| {
+                Ok(Ok(frame)) => Some(frame.format.decode::<M>(&frame.bytes).map_err(|err| {
                     Error::new(
                         ErrorKind::InvalidData,
                         format!(
@@ -237,8 +299,9 @@ where
                         ),
                     )
                 })),
+                Ok(Err(bad)) => Some(Err(Error::new(ErrorKind::InvalidData, bad))),
                 Err(e) => Some(Err(Error::new(
-                    ErrorKind::InvalidData,
+                    e.kind(),
                     format!(
                         "An error occurred while processing messages from the socket: {}",
                         e
@@ -253,10 +316,11 @@ where
     }
 }
 
+// serialized by the format of the connection
 #[inline]
-pub fn encode_message<M>(message: M) -> String
+pub fn encode_message<M>(message: M) -> Outgoing<M>
 where
     M: for<'b> serde::Serialize,
 {
-    serde_json::to_string(&message).expect("Failed to serialize a message to json")
+    Outgoing(message)
 }
@@ -2,20 +2,37 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     game::{Card, Rank, Role, Suit},
-    protocol::Username,
+    protocol::{
+        server::{MonsterLine, PileSizes, RoomCode},
+        Password, SessionToken, Username,
+    },
 };
 
 // Sent by the client to the server per context
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum IntroMsg {
-    Login(Username),
+    // the token of the last session reclaims a seat of a started game
+    Login(Username, Password, Option<SessionToken>),
+    // create an account and log in
+    Register(Username, Password),
     GetChatLog,
-    EnterGame,
+    GetRooms,
+    CreateRoom,
+    JoinRoom(RoomCode),
+    WatchRoom(RoomCode),
+    StopWatching,
 }
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum HomeMsg {
     Chat(String),
+    SetPlayerCount(u8),
+    // the host fills an empty seat with a bot
+    AddBot,
+    Ready(bool),
+    // only the host removes players and closes the lobby
+    Kick(Username),
+    Lock(bool),
     StartRoles,
 }
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -35,6 +52,8 @@ pub enum GameMsg {
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum SharedMsg {
     Ping,
+    // an answer to the heartbeat of the server
+    Pong,
     Logout,
 }
 
@@ -42,6 +61,8 @@ pub enum SharedMsg {
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct StartGame {
     pub abilities: [Option<Rank>; 3],
-    pub monsters: [Option<Card>; 2],
+    pub monsters: MonsterLine,
     pub role: Suit,
+    pub health: u16,
+    pub piles: PileSizes,
 }
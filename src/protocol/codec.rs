@@ -0,0 +1,353 @@
+use std::io::{Error, ErrorKind};
+
+use bytes::{Buf, BufMut, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::codec::{Decoder, Encoder, LinesCodec, LinesCodecError};
+
+// how the frames of a codec are divided in the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    // a frame per line of text
+    Lines,
+    // a big-endian u32 length before every frame
+    LengthPrefix,
+}
+
+// a serialization of messages on the wire. A new codec implements it
+// and gets a variant of Format to be chosen in the handshake
+pub trait Codec {
+    // a name in the capabilities of Hello
+    const NAME: &'static str;
+    const FRAMING: Framing;
+    fn encode<M: Serialize>(msg: &M) -> Result<Vec<u8>, Error>;
+    fn decode<M: DeserializeOwned>(frame: &[u8]) -> Result<M, Error>;
+}
+
+// readable frames for debugging, a message per line.
+// Every version speaks it, the handshake too
+pub struct Json;
+impl Codec for Json {
+    const NAME: &'static str = "json";
+    const FRAMING: Framing = Framing::Lines;
+    #[inline]
+    fn encode<M: Serialize>(msg: &M) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(msg).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+    #[inline]
+    fn decode<M: DeserializeOwned>(frame: &[u8]) -> Result<M, Error> {
+        serde_json::from_slice(frame).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+// compact binary frames
+pub struct MessagePack;
+impl Codec for MessagePack {
+    const NAME: &'static str = "msgpack";
+    const FRAMING: Framing = Framing::LengthPrefix;
+    #[inline]
+    fn encode<M: Serialize>(msg: &M) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec(msg).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+    #[inline]
+    fn decode<M: DeserializeOwned>(frame: &[u8]) -> Result<M, Error> {
+        rmp_serde::from_slice(frame).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+// the codec of a connection, picked at runtime
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+// runs the body with the Codec of a format as `$codec`
+macro_rules! with_codec {
+    ($format:expr, $codec:ident => $body:expr) => {
+        match $format {
+            Format::Json => {
+                type $codec = Json;
+                $body
+            }
+            Format::MessagePack => {
+                type $codec = MessagePack;
+                $body
+            }
+        }
+    };
+}
+
+impl Format {
+    // in the order of preference
+    pub const ALL: [Format; 2] = [Format::MessagePack, Format::Json];
+
+    pub fn name(self) -> &'static str {
+        with_codec!(self, C => C::NAME)
+    }
+
+    #[inline]
+    pub fn framing(self) -> Framing {
+        with_codec!(self, C => C::FRAMING)
+    }
+
+    pub fn capabilities(formats: &[Format]) -> Vec<String> {
+        formats.iter().map(|f| f.name().to_string()).collect()
+    }
+
+    // the first format of the client that the server speaks,
+    // both sides pick the same one from their Hello
+    pub fn negotiate(client: &[String], server: &[String]) -> Format {
+        client
+            .iter()
+            .filter(|c| server.contains(c))
+            .find_map(|c| Format::ALL.into_iter().find(|f| f.name() == c))
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn encode<M: Serialize>(self, msg: &M) -> Result<Vec<u8>, Error> {
+        with_codec!(self, C => C::encode(msg))
+    }
+    #[inline]
+    pub fn decode<M: DeserializeOwned>(self, frame: &[u8]) -> Result<M, Error> {
+        with_codec!(self, C => C::decode(frame))
+    }
+}
+
+// a message for the format of the connection
+pub struct Outgoing<M>(pub M);
+
+// a frame of a message and the format to decode it
+pub struct Frame {
+    pub format: Format,
+    pub bytes: BytesMut,
+}
+
+// frames of clients are small, a longer one is garbage or an attack
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024;
+// a big-endian u32 before every frame of Framing::LengthPrefix
+const LENGTH_HEAD: usize = 4;
+
+// the codec skips it, frames after it are read as usual
+#[derive(thiserror::Error, Debug)]
+pub enum BadFrame {
+    #[error("A frame is longer than the limit of {0} bytes")]
+    TooLong(usize),
+    #[error("A frame is not a line of text: {0}")]
+    NotText(Error),
+}
+
+// frames of a connection, lines of json until the handshake picks a format
+#[derive(Debug)]
+pub struct FrameCodec {
+    format: Format,
+    max_frame: usize,
+    lines: LinesCodec,
+    // bytes of an oversized binary frame that are not received yet
+    discard: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec::with_max_frame(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // a limit only for frames to read, the peer of the codec decides
+    // what it can receive
+    pub fn with_max_frame(max_frame: usize) -> Self {
+        FrameCodec {
+            format: Format::Json,
+            max_frame,
+            lines: LinesCodec::new_with_max_length(max_frame),
+            discard: 0,
+        }
+    }
+    // both sides switch right after the handshake
+    #[inline]
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    #[inline]
+    fn frame(&self, bytes: BytesMut) -> Option<Result<Frame, BadFrame>> {
+        Some(Ok(Frame {
+            format: self.format,
+            bytes,
+        }))
+    }
+
+    fn line(
+        &self,
+        line: Result<Option<String>, LinesCodecError>,
+    ) -> Result<Option<Result<Frame, BadFrame>>, Error> {
+        match line {
+            Ok(line) => Ok(line.and_then(|line| self.frame(BytesMut::from(line.as_bytes())))),
+            // the codec drops the rest of the line itself
+            Err(LinesCodecError::MaxLineLengthExceeded) => {
+                Ok(Some(Err(BadFrame::TooLong(self.max_frame))))
+            }
+            // a line of invalid utf-8 is consumed already
+            Err(LinesCodecError::Io(e)) if e.kind() == ErrorKind::InvalidData => {
+                Ok(Some(Err(BadFrame::NotText(e))))
+            }
+            Err(LinesCodecError::Io(e)) => Err(e),
+        }
+    }
+
+    fn skip(&mut self, src: &mut BytesMut) {
+        let n = self.discard.min(src.len());
+        src.advance(n);
+        self.discard -= n;
+    }
+
+    fn length_delimited(&mut self, src: &mut BytesMut) -> Option<Result<Frame, BadFrame>> {
+        if self.discard > 0 {
+            self.skip(src);
+            if self.discard > 0 {
+                return None;
+            }
+        }
+        if src.len() < LENGTH_HEAD {
+            return None;
+        }
+        let len =
+            u32::from_be_bytes(src[..LENGTH_HEAD].try_into().expect("A length head")) as usize;
+        if len > self.max_frame {
+            // never buffer it, the length is enough to skip the frame
+            src.advance(LENGTH_HEAD);
+            self.discard = len;
+            self.skip(src);
+            return Some(Err(BadFrame::TooLong(self.max_frame)));
+        }
+        if src.len() < LENGTH_HEAD + len {
+            src.reserve(LENGTH_HEAD + len - src.len());
+            return None;
+        }
+        src.advance(LENGTH_HEAD);
+        let bytes = src.split_to(len);
+        self.frame(bytes)
+    }
+}
+
+impl Decoder for FrameCodec {
+    // a bad frame does not end the stream
+    type Item = Result<Frame, BadFrame>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Error> {
+        match self.format.framing() {
+            Framing::Lines => {
+                let line = self.lines.decode(src);
+                self.line(line)
+            }
+            Framing::LengthPrefix => Ok(self.length_delimited(src)),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Error> {
+        match self.format.framing() {
+            Framing::Lines => {
+                let line = self.lines.decode_eof(src);
+                self.line(line)
+            }
+            Framing::LengthPrefix => match self.length_delimited(src) {
+                None if !src.is_empty() => Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "A frame is cut by the end of the stream",
+                )),
+                frame => Ok(frame),
+            },
+        }
+    }
+}
+
+impl<M: Serialize> Encoder<Outgoing<M>> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, Outgoing(msg): Outgoing<M>, dst: &mut BytesMut) -> Result<(), Error> {
+        let bytes = self.format.encode(&msg)?;
+        match self.format.framing() {
+            Framing::Lines => {
+                dst.reserve(bytes.len() + 1);
+                dst.put_slice(&bytes);
+                dst.put_u8(b'\n');
+            }
+            Framing::LengthPrefix => {
+                let len = u32::try_from(bytes.len())
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                dst.reserve(LENGTH_HEAD + bytes.len());
+                dst.put_u32(len);
+                dst.put_slice(&bytes);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{server, Msg};
+
+    #[test]
+    fn roundtrip_frames_in_each_format() {
+        for format in Format::ALL {
+            let mut codec = FrameCodec::new();
+            codec.set_format(format);
+            let mut buf = BytesMut::new();
+            let msg =
+                Msg::<server::SharedMsg, server::HomeMsg>::State(server::HomeMsg::PlayerCount(3));
+            codec.encode(Outgoing(msg), &mut buf).unwrap();
+            let frame = codec
+                .decode(&mut buf)
+                .unwrap()
+                .expect("A whole frame")
+                .unwrap();
+            assert!(buf.is_empty());
+            assert!(matches!(
+                format.decode(&frame.bytes).unwrap(),
+                Msg::<server::SharedMsg, server::HomeMsg>::State(server::HomeMsg::PlayerCount(3))
+            ));
+        }
+    }
+
+    #[test]
+    fn skip_bad_frames_and_read_the_next() {
+        for format in Format::ALL {
+            let mut codec = FrameCodec::with_max_frame(32);
+            codec.set_format(format);
+            let mut buf = BytesMut::new();
+            codec.encode(Outgoing("x".repeat(100)), &mut buf).unwrap();
+            codec.encode(Outgoing("ok"), &mut buf).unwrap();
+            // the oversized frame comes in parts
+            let mut src = buf.split_to(20);
+            assert!(matches!(
+                codec.decode(&mut src).unwrap(),
+                Some(Err(BadFrame::TooLong(32))) | None
+            ));
+            src.unsplit(buf);
+            let mut frames = std::iter::from_fn(|| codec.decode(&mut src).unwrap());
+            let frame = frames
+                .find_map(|f| f.ok())
+                .expect("The frame after the bad one");
+            assert_eq!(format.decode::<String>(&frame.bytes).unwrap(), "ok");
+        }
+    }
+
+    #[test]
+    fn negotiate_the_first_common_format() {
+        let all = Format::capabilities(&Format::ALL);
+        let json = Format::capabilities(&[Format::Json]);
+        assert_eq!(Format::negotiate(&all, &all), Format::MessagePack);
+        assert_eq!(Format::negotiate(&json, &all), Format::Json);
+        assert_eq!(Format::negotiate(&all, &json), Format::Json);
+        // a client before formats
+        assert_eq!(Format::negotiate(&[], &all), Format::Json);
+    }
+}
@@ -23,7 +23,7 @@ pub(crate) use impl_GameContextKind_from_state;
 
 use crate::{
     client::ui::details::{StatefulList, Statefulness},
-    protocol::RoleStatus,
+    protocol::{server, RoleStatus},
 };
 
 impl Statefulness for StatefulList<RoleStatus, [RoleStatus; 4]> {
@@ -129,3 +129,51 @@ where
         }
     }
 }
+
+impl Statefulness for StatefulList<server::LobbyPlayer, Vec<server::LobbyPlayer>> {
+    type Item<'a> = &'a server::LobbyPlayer;
+    fn next(&mut self) {
+        self.active = match self.active {
+            _ if self.items.is_empty() => None,
+            Some(i) if i < self.items.len() - 1 => Some(i + 1),
+            _ => Some(0),
+        };
+    }
+    fn prev(&mut self) {
+        self.active = match self.active {
+            _ if self.items.is_empty() => None,
+            Some(i) if i > 0 => Some(i - 1),
+            _ => Some(self.items.len() - 1),
+        };
+    }
+    fn active(&self) -> Option<Self::Item<'_>> {
+        self.active.and_then(|i| self.items.get(i))
+    }
+    fn selected(&self) -> Option<Self::Item<'_>> {
+        self.selected.and_then(|i| self.items.get(i))
+    }
+}
+
+impl Statefulness for StatefulList<server::RoomInfo, Vec<server::RoomInfo>> {
+    type Item<'a> = &'a server::RoomInfo;
+    fn next(&mut self) {
+        self.active = match self.active {
+            _ if self.items.is_empty() => None,
+            Some(i) if i < self.items.len() - 1 => Some(i + 1),
+            _ => Some(0),
+        };
+    }
+    fn prev(&mut self) {
+        self.active = match self.active {
+            _ if self.items.is_empty() => None,
+            Some(i) if i > 0 => Some(i - 1),
+            _ => Some(self.items.len() - 1),
+        };
+    }
+    fn active(&self) -> Option<Self::Item<'_>> {
+        self.active.and_then(|i| self.items.get(i))
+    }
+    fn selected(&self) -> Option<Self::Item<'_>> {
+        self.selected.and_then(|i| self.items.get(i))
+    }
+}
@@ -1,29 +1,48 @@
 use std::net::SocketAddr;
 
+use arrayvec::ArrayVec;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    game::{AbilityDeck, Card, Rank, Role},
-    protocol::{client, RoleStatus, TurnStatus, Username},
+    game::{Card, Rank, Role},
+    protocol::{
+        client, GameContextKind, GamePhaseKind, RoleStatus, SessionToken, TurnStatus, Username,
+    },
 };
 
-pub const MAX_PLAYER_COUNT: usize = 2;
+pub const MAX_PLAYER_COUNT: usize = 4;
+pub const MAX_ROOM_COUNT: usize = 16;
+pub const DEFAULT_PLAYER_COUNT: usize = 2;
 pub const ABILITY_COUNT: usize = 3;
-pub const MONSTERS_PER_LINE_COUNT: usize = 2;
+pub const MAX_HEALTH: u16 = 36;
 
 pub type PlayerId = SocketAddr;
+// a short code of a game room to join it
+pub type RoomCode = u16;
+// a monster per player on the line
+pub type MonsterLine = ArrayVec<Option<Card>, MAX_PLAYER_COUNT>;
 
 // Sent by the server to give the client
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum IntroMsg {
     LoginStatus(LoginStatus),
-    StartHome,
+    // sent before Logged, the client keeps it for reconnection
+    Session(SessionToken),
+    Rooms(Vec<RoomInfo>),
+    StartHome(RoomCode),
+    RoomRejected(EnterRoomError),
+    // a spectator view of a running game
+    Watch(GameView),
     ReconnectRoles(Option<Role>),
     ReconnectGame(client::StartGame),
 }
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum HomeMsg {
     StartRoles(Option<Role>),
+    PlayerCount(u8),
+    // players of the lobby after every change
+    Roster(Roster),
+    StartRejected(StartRolesError),
 }
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum RolesMsg {
@@ -36,28 +55,108 @@ pub enum RolesMsg {
 pub enum GameMsg {
     DropAbility(TurnResult<Rank>),
     SelectAbility(TurnResult<Rank>),
-    Attack(TurnResult<Card>),
+    Attack(TurnResult<Result<Card, AttackError>>),
     Defend(Option<Card>),
     Turn(TurnStatus),
     Continue(TurnResult<()>),
-    UpdateGameData(([Option<Card>; 2], [Option<Rank>; 3])),
+    UpdateGameData(GameData),
+    MoveRejected(MoveRejection),
+    GameOver {
+        outcome: GameOutcome,
+        stats: GameStats,
+    },
 }
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum SharedMsg {
     Pong,
+    // a heartbeat, the client answers with Pong
+    Ping,
+    // the connection is closed, also for all players after the game is over
     Logout,
+    // the server of the room has stopped and the connection is closed,
+    // a saved game continues after the next login
+    RoomCrashed,
+    // the last message before the server closes the connection
+    ServerShutdown { reason: String },
+    // the host has removed the player from the lobby, the connection is closed
+    Kicked,
     ChatLog(Vec<ChatLine>),
     Chat(ChatLine),
 }
 
-use crate::server::details::StatebleItem;
-impl StatebleItem for AbilityDeck {
-    type Item = Rank;
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GameData {
+    pub monsters: MonsterLine,
+    pub abilities: [Option<Rank>; 3],
+    pub health: u16,
+    pub piles: PileSizes,
+}
+
+// cards left in the ability draw and discard piles
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct PileSizes {
+    pub draw: u8,
+    pub discard: u8,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RoomInfo {
+    pub code: RoomCode,
+    pub state: GameContextKind,
+    pub host: Username,
+    // connected players and players expected in the room
+    pub players: u8,
+    pub player_count: u8,
 }
-impl AsRef<[Rank]> for AbilityDeck {
-    fn as_ref(&self) -> &[Rank] {
-        &self.ranks
-    }
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnterRoomError {
+    NotFound,
+    Full,
+    AlreadyStarted,
+    NotStarted,
+    RoomLimit,
+    // the host doesn't let new players in
+    Locked,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Roster {
+    // the host is the first
+    pub players: Vec<LobbyPlayer>,
+    pub locked: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LobbyPlayer {
+    pub name: Username,
+    pub ready: bool,
+}
+
+#[derive(thiserror::Error, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StartRolesError {
+    #[error("only the host can start the game")]
+    HostOnly,
+    #[error("waiting for more players")]
+    NotFull,
+    #[error("not all players are ready")]
+    NotReady,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GameView {
+    pub monsters: MonsterLine,
+    pub players: ArrayVec<PlayerView, MAX_PLAYER_COUNT>,
+    pub active_player: Username,
+    pub phase: GamePhaseKind,
+    pub outcome: Option<GameOutcome>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PlayerView {
+    pub name: Username,
+    pub role: Role,
+    pub health: u16,
 }
 
 #[derive(Deserialize, Serialize, Clone, Copy, Debug)]
@@ -68,6 +167,40 @@ pub enum SelectRoleError {
 
 pub type TurnResult<T> = Result<T, Username>;
 
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameOutcome {
+    Victory,
+    Defeat,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct GameStats {
+    pub rounds: u16,
+    pub monsters_defeated: u16,
+    pub monsters_left: u16,
+}
+
+// a move of the active player that doesn't fit the game
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveRejection {
+    GameOver,
+    // the current phase of the game
+    WrongPhase(GamePhaseKind),
+    NotInHand(Rank),
+    NoSelectedAbility,
+    NotOnMonsterLine(Card),
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub enum AttackError {
+    // another monster of the line falls to the ability, the turn stays
+    TooWeak { ability: Rank, monster: Card },
+    // no monster falls to it, the ability is discarded and the turn is lost
+    NoTarget { ability: Rank },
+    // the monster has no unseen one of its kind to swap with, the turn stays
+    NothingToSwap { monster: Card },
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum ChatLine {
     Text(String),
@@ -84,4 +217,9 @@ pub enum LoginStatus {
     InvalidPlayerName,
     AlreadyLogged,
     PlayerLimit,
+    // unknown username or wrong password
+    BadCredentials,
+    AccountExists,
+    // a seat of a started game without the session token of its player
+    BadSession,
 }
@@ -7,12 +7,16 @@ use tokio::{
     sync::mpsc::{channel, Receiver, Sender},
     time::{self, Duration},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
+pub mod accounts;
 pub mod details;
 pub mod peer;
+pub mod snapshot;
 pub mod states;
 
 pub const MPSC_CHANNEL_CAPACITY: usize = 32;
+// how long open connections are waited on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 pub type Answer<T> = tokio::sync::oneshot::Sender<T>;
 pub type Rx<T> = Receiver<T>;
 pub type Tx<T> = Sender<T>;
@@ -48,22 +52,34 @@ impl<T> Clone for Handle<T> {
 
 pub async fn listen(
     addr: SocketAddr,
+    intro: states::IntroServer,
+    heartbeat: peer::Heartbeat,
+    limits: peer::Limits,
     shutdown: impl Future<Output = std::io::Result<()>>,
 ) -> anyhow::Result<()> {
     let listener = TcpListener::bind(&addr)
         .await
         .with_context(|| format!("Failed to bind a socket to {}", addr))?;
-    info!("Listening on: {}", addr);
+    serve(listener, intro, heartbeat, limits, shutdown).await
+}
+
+// accepts connections of a bound listener until the shutdown signal
+pub async fn serve(
+    listener: TcpListener,
+    intro: states::IntroServer,
+    heartbeat: peer::Heartbeat,
+    limits: peer::Limits,
+    shutdown: impl Future<Output = std::io::Result<()>>,
+) -> anyhow::Result<()> {
+    info!("Listening on: {}", listener.local_addr()?);
     let (tx, rx) = channel(MPSC_CHANNEL_CAPACITY);
     let mut join_server = tokio::spawn(async move {
-        states::run_intro_server(&mut states::StartServer::new(
-            states::IntroServer::default(),
-            rx,
-        ))
-        .await
+        states::run_intro_server(&mut states::StartServer::new(intro, rx)).await
     });
 
     let server_handle = states::IntroHandle::for_tx(tx);
+    // every connection task holds a sender, the channel closes when all of them end
+    let (connections_tx, mut connections_done) = channel::<()>(1);
 
     let server_end = tokio::select! {
         server_result = &mut join_server => {
@@ -86,14 +102,16 @@ pub async fn listen(
                         Ok((mut stream, addr)) => {
                             tokio::spawn({
                                 let server_handle = server_handle.clone();
+                                let connection = connections_tx.clone();
                                 async move {
                                 if let Err(err) = peer::accept_connection(&mut stream,
-                                                                   server_handle)
+                                                                   server_handle, heartbeat, limits)
                                     .await {
                                         error!("Failed to accept = {:#}", err);
                                 }
                                 let _ = stream.shutdown().await;
                                 info!(?addr, "Disconnected");
+                                drop(connection);
                             }});
                             break 'try_connect;
                          }
@@ -115,9 +133,25 @@ pub async fn listen(
             Ok(())
         }
     };
-    // TODO if error here program may leaks
-    // send shutdown signal to the server actor and wait
-    let shutdown = server_handle.shutdown().await.context("Failed to shutdown");
+    let reason = match &server_end {
+        Ok(()) => "The server is shutting down".to_string(),
+        Err(e) => format!("The server has failed: {}", e),
+    };
+    // send shutdown signal to the server actor and wait,
+    // it notifies clients and saves running games
+    let shutdown = server_handle
+        .shutdown(reason)
+        .await
+        .context("Failed to shutdown");
+    drop(server_handle);
+    // let connections flush their socket queues
+    drop(connections_tx);
+    if time::timeout(SHUTDOWN_TIMEOUT, connections_done.recv())
+        .await
+        .is_err()
+    {
+        warn!("Close the server with open connections");
+    }
     server_end?;
     shutdown
 }
@@ -129,93 +163,271 @@ mod tests {
 
     use anyhow::anyhow;
     use tokio::{
-        net::{ TcpStream, tcp::{ReadHalf, WriteHalf}},
+        io::{AsyncRead, AsyncWrite},
+        net::{ TcpStream, tcp::{OwnedReadHalf, OwnedWriteHalf, ReadHalf, WriteHalf}},
         task::JoinHandle,
         time::{sleep, Duration},
     };
     use tokio_util::sync::CancellationToken;
-    use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+    use tokio_util::codec::{FramedRead, FramedWrite};
     use futures::SinkExt;
     use tracing_test::traced_test;
     use tracing::debug;
 
     use super::*;
-    use crate::protocol::{Msg, With, server, server::LoginStatus, Username, MessageDecoder, encode_message, client};
+    use crate::game::{Card, Deck, MonsterDeck, Rank, Suit};
+    use crate::protocol::{Msg, With, server, server::LoginStatus, Username, Password, SessionToken, MessageDecoder, encode_message, client, Hello, Welcome, codec::{Format, FrameCodec}};
+
+    type Reader<R = OwnedReadHalf> = MessageDecoder<FramedRead<R, FrameCodec>>;
+    type Writer<W = OwnedWriteHalf> = FramedWrite<W, FrameCodec>;
 
-    fn host() -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080)
+    fn username(name: &str) -> Username {
+        Username::new(arraystring::ArrayString::try_from_str(name).unwrap()).unwrap()
     }
-    fn spawn_server(cancel: CancellationToken) -> JoinHandle<anyhow::Result<()>> {
-        tokio::spawn(async move {
-            listen(host(), async move {
+    // tests run in parallel, every server takes a free port
+    async fn spawn_server(cancel: CancellationToken) -> (SocketAddr, JoinHandle<anyhow::Result<()>>) {
+        spawn_server_with(states::IntroServer::default(), peer::Heartbeat::default(), peer::Limits::default(), cancel).await
+    }
+    async fn spawn_server_with(
+        intro: states::IntroServer,
+        heartbeat: peer::Heartbeat,
+        limits: peer::Limits,
+        cancel: CancellationToken,
+    ) -> (SocketAddr, JoinHandle<anyhow::Result<()>>) {
+        let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        (addr, tokio::spawn(async move {
+            serve(listener, intro, heartbeat, limits, async move {
                 cancel.cancelled().await;
                 Ok(())
             })
             .await
-        })
+        }))
     }
     fn spawn_simple_client(
+        addr: SocketAddr,
         username: String,
         cancel: CancellationToken,
     ) -> JoinHandle<anyhow::Result<()>> {
         tokio::spawn(async move {
-            let mut socket = TcpStream::connect(host()).await.unwrap();
-            let (mut r, mut w) = split_to_read_write(&mut socket);
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            let (mut r, mut w) = split_to_read_write(&mut socket).await;
             login(username, &mut w, &mut r).await?;
             cancel.cancelled().await;
             Ok::<(), anyhow::Error>(())
         })
     }
-    fn split_to_read_write(
+    // frames of a connection after the handshake
+    async fn split_to_read_write(
         socket: &mut TcpStream,
     ) -> (
-        MessageDecoder<FramedRead<ReadHalf<'_>, LinesCodec>>,
-        FramedWrite<WriteHalf<'_>, LinesCodec>,
+        Reader<ReadHalf<'_>>,
+        Writer<WriteHalf<'_>>,
     ) {
         let (r, w) = socket.split();
+        handshake(
+            MessageDecoder::new(FramedRead::new(r, FrameCodec::new())),
+            FramedWrite::new(w, FrameCodec::new()),
+        )
+        .await
+    }
+    // frames of a connection before the handshake
+    async fn frames(addr: SocketAddr) -> (Reader, Writer) {
+        let (r, w) = TcpStream::connect(addr).await.unwrap().into_split();
         (
-            MessageDecoder::new(FramedRead::new(r, LinesCodec::new())),
-            FramedWrite::new(w, LinesCodec::new()),
+            MessageDecoder::new(FramedRead::new(r, FrameCodec::new())),
+            FramedWrite::new(w, FrameCodec::new()),
         )
     }
-    async fn login(
-        username: String,
-        w: &mut FramedWrite<WriteHalf<'_>, LinesCodec>,
-        r: &mut MessageDecoder<FramedRead<ReadHalf<'_>, LinesCodec>>,
-    ) -> anyhow::Result<()> {
-        w.send(encode_message(Msg::with(client::IntroMsg::Login(
-            Username::new(arraystring::ArrayString::try_from_str(username).unwrap()).unwrap(),
-        ))))
-        .await
-        .unwrap();
-        if let Msg::State(server::IntroMsg::LoginStatus(status)) = r
-            .next::<Msg<server::SharedMsg, server::IntroMsg>>()
+    async fn connect(addr: SocketAddr) -> (Reader, Writer) {
+        let (r, w) = frames(addr).await;
+        handshake(r, w).await
+    }
+    async fn handshake<R, W>(mut r: Reader<R>, mut w: Writer<W>) -> (Reader<R>, Writer<W>)
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let hello = Hello::default();
+        w.send(encode_message(&hello)).await.unwrap();
+        let Some(Ok(Ok(server))) = r.next::<Welcome>().await else {
+            panic!("Server must accept Hello");
+        };
+        let format = Format::negotiate(&hello.capabilities, &server.capabilities);
+        w.encoder_mut().set_format(format);
+        r.set_format(format);
+        (r, w)
+    }
+    async fn connect_as(addr: SocketAddr, username: &str) -> (Reader, Writer) {
+        let (mut r, mut w) = connect(addr).await;
+        login(username.into(), &mut w, &mut r).await.unwrap();
+        (r, w)
+    }
+    // skips messages until `f` takes one, None at the end of the stream
+    async fn next_matching<M, T, R>(r: &mut Reader<R>, mut f: impl FnMut(M) -> Option<T>) -> Option<T>
+    where
+        M: for<'b> serde::Deserialize<'b>,
+        R: AsyncRead + Unpin,
+    {
+        loop {
+            if let Ok(msg) = r.next::<M>().await? {
+                if let Some(found) = f(msg) {
+                    return Some(found);
+                }
+            }
+        }
+    }
+    // state messages of a client in a row
+    async fn send_all<M: serde::Serialize, W: AsyncWrite + Unpin>(w: &mut Writer<W>, msgs: impl IntoIterator<Item = M>) {
+        for msg in msgs {
+            w.send(encode_message(Msg::<client::SharedMsg, M>::State(msg))).await.unwrap();
+        }
+    }
+    async fn send_credentials_for_session<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        msg: client::IntroMsg,
+        w: &mut Writer<W>,
+        r: &mut Reader<R>,
+    ) -> anyhow::Result<(LoginStatus, Option<SessionToken>)> {
+        w.send(encode_message(Msg::<client::SharedMsg, client::IntroMsg>::with(msg)))
             .await
-            .context("A Socket must be connected")?
-            .context("Must be a message")?
-        {
-            debug!("Test client login status {:?}", status);
-            if status == LoginStatus::Logged {
-                Ok(())
-            } else {
-                Err(anyhow!("Failed to login {:?}", status))
+            .unwrap();
+        let mut session = None;
+        loop {
+            match r
+                .next::<Msg<server::SharedMsg, server::IntroMsg>>()
+                .await
+                .context("A Socket must be connected")?
+                .context("Must be a message")?
+            {
+                Msg::State(server::IntroMsg::Session(token)) => session = Some(token),
+                Msg::State(server::IntroMsg::LoginStatus(status)) => {
+                    debug!("Test client login status {:?}", status);
+                    return Ok((status, session));
+                }
+                _ => return Err(anyhow!("Login status not received")),
             }
-        } else {
-            Err(anyhow!("Login status not received"))
         }
     }
+    async fn send_credentials<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        msg: client::IntroMsg,
+        w: &mut Writer<W>,
+        r: &mut Reader<R>,
+    ) -> anyhow::Result<LoginStatus> {
+        send_credentials_for_session(msg, w, r).await.map(|(status, _)| status)
+    }
+    // register a new account or log in to the existing one
+    async fn login_status<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        username: String,
+        w: &mut Writer<W>,
+        r: &mut Reader<R>,
+    ) -> anyhow::Result<LoginStatus> {
+        let username = self::username(&username);
+        let password = Password::from(format!("{}-password", username));
+        match send_credentials(client::IntroMsg::Register(username.clone(), password.clone()), w, r).await? {
+            LoginStatus::AccountExists => {
+                send_credentials(client::IntroMsg::Login(username, password, None), w, r).await
+            }
+            status => Ok(status),
+        }
+    }
+    async fn login<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        username: String,
+        w: &mut Writer<W>,
+        r: &mut Reader<R>,
+    ) -> anyhow::Result<()> {
+        match login_status(username, w, r).await? {
+            LoginStatus::Logged => Ok(()),
+            status => Err(anyhow!("Failed to login {:?}", status)),
+        }
+    }
+    async fn ping(w: &mut Writer, r: &mut Reader) -> bool {
+        w.send(encode_message(Msg::<client::SharedMsg, client::IntroMsg>::with(client::SharedMsg::Ping)))
+            .await
+            .unwrap();
+        matches!(
+            r.next::<Msg<server::SharedMsg, server::IntroMsg>>().await,
+            Some(Ok(Msg::Shared(server::SharedMsg::Pong)))
+        )
+    }
+    async fn shutdown(socket: &mut (impl AsyncWrite + Unpin), cancel: CancellationToken) {
+        let _ = socket.shutdown().await;
+        sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+    }
+
+    async fn enter_room<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        room: Option<server::RoomCode>,
+        w: &mut Writer<W>,
+        r: &mut Reader<R>,
+    ) -> anyhow::Result<Result<server::RoomCode, server::EnterRoomError>> {
+        send_all(w, [match room {
+            Some(code) => client::IntroMsg::JoinRoom(code),
+            None => client::IntroMsg::CreateRoom,
+        }]).await;
+        next_matching(r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::IntroMsg::StartHome(code)) => Some(Ok(code)),
+            Msg::State(server::IntroMsg::RoomRejected(e)) => Some(Err(e)),
+            // skip messages of the Home state
+            _ => None,
+        })
+        .await
+        .context("A Socket must be connected")
+    }
+    fn spawn_room_client(
+        addr: SocketAddr,
+        username: String,
+        room: Option<server::RoomCode>,
+        entered: tokio::sync::oneshot::Sender<server::RoomCode>,
+        cancel: CancellationToken,
+    ) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(async move {
+            let (mut r, mut w) = connect(addr).await;
+            login(username, &mut w, &mut r).await?;
+            let code = enter_room(room, &mut w, &mut r)
+                .await?
+                .map_err(|e| anyhow!("Failed to enter the room {:?}", e))?;
+            send_all(&mut w, [client::HomeMsg::Ready(true)]).await;
+            let _ = entered.send(code);
+            cancel.cancelled().await;
+            Ok::<(), anyhow::Error>(())
+        })
+    }
+    // a player in the lobby of a new room starts a game for one
+    async fn start_game_alone(w: &mut Writer, r: &mut Reader) -> client::StartGame {
+        send_all(w, [
+            client::HomeMsg::SetPlayerCount(1),
+            client::HomeMsg::Ready(true),
+            client::HomeMsg::StartRoles,
+        ]).await;
+        next_matching(r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::StartRoles(_)) => Some(()),
+            _ => None,
+        })
+        .await
+        .expect("The roles were not started");
+        send_all(w, [
+            client::RolesMsg::Select(crate::game::Role::Warrior),
+            client::RolesMsg::StartGame,
+        ]).await;
+        next_matching(r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::RolesMsg::StartGame(game)) => Some(game),
+            _ => None,
+        })
+        .await
+        .expect("The game was not started")
+    }
 
     #[traced_test]
     #[tokio::test]
     async fn accept_connection_and_disconnection() {
         let cancel_token = CancellationToken::new();
-        let server = spawn_server(cancel_token.clone());
+        let (addr, server) = spawn_server(cancel_token.clone()).await;
         let mut clients = Vec::new();
         for i in 0..2 {
             let cancel = cancel_token.clone();
             clients.push(tokio::spawn(async move {
-                let mut socket = TcpStream::connect(host()).await.unwrap();
-                let (mut r, mut w) = split_to_read_write(&mut socket);
+                let mut socket = TcpStream::connect(addr).await.unwrap();
+                let (mut r, mut w) = split_to_read_write(&mut socket).await;
                 w.send(encode_message(Msg::<client::SharedMsg,client::IntroMsg>
                                       ::with(client::SharedMsg::Ping)))
                     .await
@@ -257,32 +469,37 @@ mod tests {
     #[tokio::test]
     async fn reject_login_with_existing_username() {
         let cancel_token = CancellationToken::new();
-        let server = spawn_server(cancel_token.clone());
+        let (addr, server) = spawn_server(cancel_token.clone()).await;
+        // the second client logs in after the first one
+        let logged = std::sync::Arc::new(tokio::sync::Barrier::new(2));
         let mut clients = Vec::new();
         for i in 0..2 {
             let cancel = cancel_token.clone();
+            let logged = logged.clone();
             clients.push(tokio::spawn(async move {
-                let mut socket = TcpStream::connect(host()).await.unwrap();
-                let (mut r, mut w) = split_to_read_write(&mut socket);
-                w.send(encode_message(Msg::<client::SharedMsg, client::IntroMsg>
-                                      ::with(client::IntroMsg::Login(
-                    Username::new(arraystring::ArrayString::try_from_str("Ig").unwrap()).unwrap(),
-                ))))
-                .await
-                .unwrap();
-                let result_message = r
-                    .next::<Msg<server::SharedMsg, server::IntroMsg>>()
+                let mut socket = TcpStream::connect(addr).await.unwrap();
+                let (mut r, mut w) = split_to_read_write(&mut socket).await;
+                if i == 1 {
+                    logged.wait().await;
+                }
+                let result_message = login_status("Ig".into(), &mut w, &mut r)
                     .await
                     .context("the server must send a LoginStatus message to the client");
+                if i == 0 {
+                    logged.wait().await;
+                }
+                // stay logged while the second client tries the same account
+                if i == 0 {
+                    sleep(Duration::from_millis(100)).await;
+                }
                 let _ = socket.shutdown().await;
                 // wait a disconnection of the last client and shutdown the server
                 if i == 1 {
                     sleep(Duration::from_millis(200)).await;
                     cancel.cancel();
                 }
-                match result_message? {
-                    Ok(Msg::State(server::IntroMsg::LoginStatus(status))) => {
-                        use crate::protocol::server::LoginStatus;
+                match result_message {
+                    Ok(status) => {
                         match i {
                             0 => match status {
                                 LoginStatus::Logged => Ok(()),
@@ -298,7 +515,6 @@ mod tests {
                         }
                     }
                     Err(e) => Err(anyhow!("Error = {}", e)),
-                    _ => Err(anyhow!("Unexpected message from the server")),
                 }
             }));
         }
@@ -317,43 +533,249 @@ mod tests {
             }
         }
     }
-    async fn shutdown(socket: &mut TcpStream, cancel: CancellationToken) {
-        let _ = socket.shutdown().await;
+    #[traced_test]
+    #[tokio::test]
+    async fn drop_silent_client() {
+        let cancel = CancellationToken::new();
+        let heartbeat = peer::Heartbeat {
+            interval: Duration::from_millis(100),
+            max_missed: 2,
+        };
+        let (addr, server) = spawn_server_with(states::IntroServer::default(), heartbeat, peer::Limits::default(), cancel.clone()).await;
+        // a half-open connection never answers pings
+        let _silent = connect_as(addr, "Ig").await;
+        sleep(Duration::from_millis(500)).await;
+
+        let (mut r, mut w) = connect(addr).await;
+        let status = login_status("Ig".into(), &mut w, &mut r).await.unwrap();
+        assert_eq!(status, LoginStatus::Logged, "The silent peer must be dropped");
+        cancel.cancel();
+        server.await.unwrap().unwrap();
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn drop_client_without_hello() {
+        let cancel = CancellationToken::new();
+        let heartbeat = peer::Heartbeat {
+            interval: Duration::from_millis(100),
+            max_missed: 2,
+        };
+        let (addr, server) = spawn_server_with(states::IntroServer::default(), heartbeat, peer::Limits::default(), cancel.clone()).await;
+        // connected but never says Hello
+        let (mut r, _w) = frames(addr).await;
+        let closed = time::timeout(Duration::from_secs(2), r.next::<Welcome>()).await;
+        cancel.cancel();
+        server.await.unwrap().unwrap();
+        assert!(matches!(closed, Ok(None)), "The socket must be closed by the server");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn notify_clients_on_shutdown() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut ir, _iw) = connect_as(addr, "Ig").await;
+        let (mut rr, mut rw) = connect_as(addr, "Kat").await;
+        enter_room(None, &mut rw, &mut rr).await.unwrap().unwrap();
         sleep(Duration::from_millis(100)).await;
         cancel.cancel();
+
+        for r in [&mut ir, &mut rr] {
+            let mut reason = None;
+            // the last message before the end of the stream
+            while let Some(msg) = r.next::<Msg<server::SharedMsg, server::HomeMsg>>().await {
+                if let Ok(Msg::Shared(server::SharedMsg::ServerShutdown { reason: r })) = msg {
+                    reason = Some(r);
+                }
+            }
+            assert!(reason.is_some(), "A client must get the reason of shutdown");
+        }
+        server.await.unwrap().unwrap();
     }
+    #[traced_test]
+    #[tokio::test]
+    async fn reject_wrong_password() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (_, mut w) = connect_as(addr, "Ig").await;
+        let _ = w.get_mut().shutdown().await;
+        sleep(Duration::from_millis(100)).await;
 
+        let (mut r, mut w) = connect(addr).await;
+        let guess = || Password::from("guess".to_string());
+        let wrong = send_credentials(
+            client::IntroMsg::Login(username("Ig"), guess(), None), &mut w, &mut r).await.unwrap();
+        let unknown = send_credentials(
+            client::IntroMsg::Login(username("Ks"), guess(), None), &mut w, &mut r).await.unwrap();
+        let taken = send_credentials(
+            client::IntroMsg::Register(username("Ig"), guess()), &mut w, &mut r).await.unwrap();
+        // the connection stays open for a next attempt
+        let retry = login_status("Ig".into(), &mut w, &mut r).await.unwrap();
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert_eq!(wrong, LoginStatus::BadCredentials, "Wrong password was accepted");
+        assert_eq!(unknown, LoginStatus::BadCredentials, "Unknown user was accepted");
+        assert_eq!(taken, LoginStatus::AccountExists, "Account was registered twice");
+        assert_eq!(retry, LoginStatus::Logged, "Failed to login after a wrong password");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn close_the_connection_after_failed_logins() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (_, mut w) = connect_as(addr, "Ig").await;
+        let _ = w.get_mut().shutdown().await;
+        sleep(Duration::from_millis(100)).await;
+
+        let (mut r, mut w) = connect(addr).await;
+        let mut statuses = Vec::new();
+        for _ in 0..5 {
+            statuses.push(send_credentials(
+                client::IntroMsg::Login(username("Ig"), Password::from("guess".to_string()), None), &mut w, &mut r)
+                .await
+                .unwrap());
+        }
+        // the server closes the connection, the rest of the stream is skipped
+        let closed = tokio::time::timeout(
+            Duration::from_secs(5),
+            next_matching(&mut r, |_: Msg<server::SharedMsg, server::IntroMsg>| None::<()>),
+        )
+        .await;
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(statuses.iter().all(|s| *s == LoginStatus::BadCredentials));
+        assert!(matches!(closed, Ok(None)), "The connection must be closed");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn reject_client_of_other_protocol_version() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut r, mut w) = frames(addr).await;
+        let hello = Hello {
+            protocol_version: crate::protocol::PROTOCOL_VERSION + 1,
+            ..Default::default()
+        };
+        w.send(encode_message(hello)).await.unwrap();
+        let welcome = r.next::<Welcome>().await;
+        // the server closes the connection
+        let closed = r.next::<Welcome>().await.is_none();
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(matches!(welcome, Some(Ok(Err(crate::protocol::VersionMismatch { .. })))));
+        assert!(closed, "A rejected client must be disconnected");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn speak_json_with_client_before_formats() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut r, mut w) = frames(addr).await;
+        let hello = Hello {
+            capabilities: Vec::new(),
+            ..Default::default()
+        };
+        w.send(encode_message(hello)).await.unwrap();
+        let welcome = r.next::<Welcome>().await;
+        // frames stay json lines
+        let pong = ping(&mut w, &mut r).await;
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(matches!(welcome, Some(Ok(Ok(_)))));
+        assert!(pong);
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn drop_client_flooding_bad_frames() {
+        let cancel = CancellationToken::new();
+        let limits = peer::Limits {
+            max_frame: 256,
+            max_bad_frames: 3,
+            ..Default::default()
+        };
+        let (addr, server) = spawn_server_with(states::IntroServer::default(), peer::Heartbeat::default(), limits, cancel.clone()).await;
+        let (mut or, mut ow) = connect_as(addr, "Ig").await;
+        let (mut r, mut w) = connect_as(addr, "Kat").await;
+        // not a message of msgpack
+        w.get_mut().write_all(&[0, 0, 0, 1, 0xc1]).await.unwrap();
+        assert!(ping(&mut w, &mut r).await, "A bad frame is skipped");
+        // longer than the limit
+        w.get_mut().write_all(&1000u32.to_be_bytes()).await.unwrap();
+        w.get_mut().write_all(&[0; 1000]).await.unwrap();
+        w.get_mut().write_all(&[0, 0, 0, 1, 0xc1]).await.unwrap();
+        assert!(matches!(r.next::<Msg<server::SharedMsg, server::IntroMsg>>().await, None | Some(Err(_))),
+            "The peer must be dropped after 3 bad frames");
+
+        assert!(ping(&mut ow, &mut or).await, "Other peers stay");
+        let (mut ar, mut aw) = connect(addr).await;
+        let status = login_status("Kat".into(), &mut aw, &mut ar).await.unwrap();
+        assert_eq!(status, LoginStatus::Logged, "The dropped peer must log out");
+        shutdown(ow.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn drop_client_flooding_the_chat() {
+        let cancel = CancellationToken::new();
+        let limits = peer::Limits {
+            max_chat_lines: 2,
+            max_bad_frames: 2,
+            ..Default::default()
+        };
+        let (addr, server) = spawn_server_with(states::IntroServer::default(), peer::Heartbeat::default(), limits, cancel.clone()).await;
+        let (mut r, mut w) = connect_as(addr, "Ig").await;
+        enter_room(None, &mut w, &mut r).await.unwrap().unwrap();
+        let pong = |msg: Msg<server::SharedMsg, server::HomeMsg>| match msg {
+            Msg::Shared(server::SharedMsg::Pong) => Some(()),
+            _ => None,
+        };
+        send_all(&mut w, (0..3).map(|i| client::HomeMsg::Chat(format!("line {}", i)))).await;
+        w.send(encode_message(Msg::<client::SharedMsg, client::HomeMsg>::with(client::SharedMsg::Ping)))
+            .await
+            .unwrap();
+        assert!(next_matching(&mut r, pong).await.is_some(), "An extra line is only dropped");
+        send_all(&mut w, [client::HomeMsg::Chat("one more".into())]).await;
+        assert!(
+            time::timeout(Duration::from_secs(2), next_matching(&mut r, pong)).await.unwrap().is_none(),
+            "The peer must be dropped after 2 extra lines"
+        );
+        cancel.cancel();
+        server.await.unwrap().unwrap();
+    }
     #[traced_test]
     #[tokio::test]
     async fn drop_peer_actor_after_logout() {
         let cancel_token = CancellationToken::new();
-        let server = spawn_server(cancel_token.clone());
+        let (addr, server) = spawn_server(cancel_token.clone()).await;
         let client = tokio::spawn(async move {
             let client2_cancel = CancellationToken::new();
-            spawn_simple_client("Ig".into(), cancel_token.clone());
-            let client2 = spawn_simple_client("We".into(), client2_cancel.clone());
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            spawn_room_client(addr, "Ig".into(), None, tx, cancel_token.clone());
+            let code = rx.await.context("The first client must create a room")?;
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let client2 = spawn_room_client(addr, "We".into(), Some(code), tx, client2_cancel.clone());
+            rx.await.context("The second client must join the room")?;
 
-            sleep(Duration::from_millis(100)).await;
-            let mut socket = TcpStream::connect(host()).await.unwrap();
-            let (mut r, mut w) = split_to_read_write(&mut socket);
-            if login("Ks".into(), &mut w, &mut r).await.is_ok() {
-                debug!("test client was logged but it is unexpected");
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            let (mut r, mut w) = split_to_read_write(&mut socket).await;
+            // other rooms are still open
+            login("Ks".into(), &mut w, &mut r).await?;
+            if enter_room(Some(code), &mut w, &mut r).await? != Err(server::EnterRoomError::Full) {
+                debug!("test client entered the room but it is unexpected");
                 shutdown(&mut socket, cancel_token).await;
                 return Err(anyhow!(
-                    "3 client must not logged, the server should be full"
+                    "3 client must not enter the room, the room should be full"
                 ));
             }
             // disconnect a second client
             client2_cancel.cancel();
             let _ = client2.await;
+            sleep(Duration::from_millis(100)).await;
 
-            let mut socket = TcpStream::connect(host()).await.unwrap();
-            let (mut r, mut w) = split_to_read_write(&mut socket);
-            if let Err(e) = login("Ks".into(), &mut w, &mut r).await {
-                debug!("Test client login Error {}", e);
+            if let Err(e) = enter_room(Some(code), &mut w, &mut r).await? {
+                debug!("Test client enter room Error {:?}", e);
                 shutdown(&mut socket, cancel_token).await;
                 return Err(anyhow!(
-                    "Must login after a second player will disconnected"
+                    "Must enter the room after a second player will disconnected"
                 ));
             }
 
@@ -367,5 +789,605 @@ mod tests {
             Err(e) => panic!("unexpected eror {}", e),
         }
     }
-}
 
+    #[traced_test]
+    #[tokio::test]
+    async fn list_open_rooms() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        spawn_room_client(addr, "Ig".into(), None, tx, cancel.clone());
+        let code = rx.await.expect("The client must create a room");
+        // logged players without a room are not listed
+        spawn_simple_client(addr, "We".into(), cancel.clone());
+        sleep(Duration::from_millis(100)).await;
+
+        let (mut r, mut w) = connect_as(addr, "Ks").await;
+        send_all(&mut w, [client::IntroMsg::GetRooms]).await;
+        let rooms = next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::IntroMsg::Rooms(rooms)) => Some(rooms),
+            _ => None,
+        })
+        .await;
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        let rooms = rooms.expect("Rooms were not received");
+        assert!(matches!(
+            rooms.as_slice(),
+            [room] if room.code == code
+                && room.host.to_string() == "Ig"
+                && room.players == 1
+                && room.player_count == 2
+        ), "Unexpected rooms {:?}", rooms);
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn watch_a_running_game() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut pr, mut pw) = connect_as(addr, "Ig").await;
+        let code = enter_room(None, &mut pw, &mut pr).await.unwrap().unwrap();
+
+        let (mut r, mut w) = connect_as(addr, "Ks").await;
+        send_all(&mut w, [client::IntroMsg::WatchRoom(code)]).await;
+        let not_started = r.next::<Msg<server::SharedMsg, server::IntroMsg>>().await;
+        start_game_alone(&mut pw, &mut pr).await;
+
+        send_all(&mut w, [client::IntroMsg::WatchRoom(code)]).await;
+        // skip the chat log
+        let view = next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::IntroMsg::Watch(view)) => Some(Ok(view)),
+            Msg::State(msg) => Some(Err(msg)),
+            _ => None,
+        })
+        .await;
+        let _ = pw.get_mut().shutdown().await;
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(matches!(
+            not_started,
+            Some(Ok(Msg::State(server::IntroMsg::RoomRejected(server::EnterRoomError::NotStarted))))
+        ), "Only a running game can be watched");
+        let view = view.expect("A socket must be connected").expect("A view of the game");
+        assert!(matches!(
+            view.players.as_slice(),
+            [p] if p.name.to_string() == "Ig" && p.health == server::MAX_HEALTH
+        ), "Unexpected game view {:?}", view);
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn reclaim_seat_with_session_token() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let ig = username("Ig");
+        let password = Password::from("secret".to_string());
+        let (mut pr, mut pw) = connect(addr).await;
+        let (status, token) = send_credentials_for_session(
+            client::IntroMsg::Register(ig.clone(), password.clone()), &mut pw, &mut pr).await.unwrap();
+        assert_eq!(status, LoginStatus::Logged);
+        let token = token.expect("A new player must get a session token");
+        let code = enter_room(None, &mut pw, &mut pr).await.unwrap().unwrap();
+        // the second player keeps the room open
+        let (entered, joined) = tokio::sync::oneshot::channel();
+        let ks = spawn_room_client(addr, "Ks".into(), Some(code), entered, cancel.clone());
+        joined.await.unwrap();
+        // start the Roles state and leave it
+        send_all(&mut pw, [client::HomeMsg::Ready(true), client::HomeMsg::StartRoles]).await;
+        next_matching(&mut pr, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::StartRoles(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+        let _ = pw.get_mut().shutdown().await;
+        sleep(Duration::from_millis(100)).await;
+
+        // the right password is not enough to take the seat
+        let (mut r, mut w) = connect(addr).await;
+        let stranger = send_credentials(
+            client::IntroMsg::Login(ig.clone(), password.clone(), None), &mut w, &mut r).await.unwrap();
+        let _ = w.get_mut().shutdown().await;
+
+        let (mut r, mut w) = connect(addr).await;
+        let (owner, same_token) = send_credentials_for_session(
+            client::IntroMsg::Login(ig, password, Some(token.clone())), &mut w, &mut r).await.unwrap();
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        ks.await.unwrap().unwrap();
+        assert_eq!(stranger, LoginStatus::BadSession, "The seat was taken without a session");
+        assert_eq!(owner, LoginStatus::Logged, "Failed to reclaim the seat");
+        assert_eq!(same_token, Some(token), "The session must stay the same");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn restore_game_after_restart() {
+        let dir = std::env::temp_dir().join(format!("kobuleti-snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let intro = || states::IntroServer::new(
+            accounts::Accounts::load(dir.join("accounts.json")).unwrap(),
+            Some(snapshot::Snapshots::new(dir.clone())));
+        let ig = username("Ig");
+        let password = Password::from("secret".to_string());
+
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server_with(intro(), peer::Heartbeat::default(), peer::Limits::default(), cancel.clone()).await;
+        let (mut pr, mut pw) = connect(addr).await;
+        let (_, token) = send_credentials_for_session(
+            client::IntroMsg::Register(ig.clone(), password.clone()), &mut pw, &mut pr).await.unwrap();
+        let code = enter_room(None, &mut pw, &mut pr).await.unwrap().unwrap();
+        let started = start_game_alone(&mut pw, &mut pr).await;
+        sleep(Duration::from_millis(100)).await;
+        // the server stops while the game is running
+        cancel.cancel();
+        server.await.unwrap().unwrap();
+        drop((pr, pw));
+
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server_with(intro(), peer::Heartbeat::default(), peer::Limits::default(), cancel.clone()).await;
+        let (mut r, mut w) = connect(addr).await;
+        let status = send_credentials(
+            client::IntroMsg::Login(ig, password, token), &mut w, &mut r).await.unwrap();
+        send_all(&mut w, [client::IntroMsg::JoinRoom(code)]).await;
+        let restored = loop {
+            match r.next::<Msg<server::SharedMsg, server::IntroMsg>>().await {
+                Some(Ok(Msg::State(server::IntroMsg::ReconnectGame(game)))) => break Some(game),
+                Some(Ok(Msg::Shared(_))) => (),
+                _ => break None,
+            }
+        };
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status, LoginStatus::Logged);
+        let restored = restored.expect("The game must be restored");
+        assert_eq!(restored.abilities, started.abilities);
+        assert_eq!(restored.health, started.health);
+        assert_eq!(restored.role, started.role);
+    }
+    // a game of Ig alone restored from a snapshot that the test changes
+    struct RestoredGame {
+        addr: SocketAddr,
+        dir: std::path::PathBuf,
+        cancel: CancellationToken,
+        server: JoinHandle<anyhow::Result<()>>,
+        r: Reader,
+        w: Writer,
+    }
+    impl RestoredGame {
+        async fn close(mut self) {
+            shutdown(self.w.get_mut(), self.cancel).await;
+            self.server.await.unwrap().unwrap();
+            std::fs::remove_dir_all(&self.dir).unwrap();
+        }
+    }
+    async fn restore_edited_game(name: &str, edit: impl FnOnce(&mut snapshot::GameSnapshot)) -> RestoredGame {
+        let dir = std::env::temp_dir().join(format!("kobuleti-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let intro = || states::IntroServer::new(
+            accounts::Accounts::load(dir.join("accounts.json")).unwrap(),
+            Some(snapshot::Snapshots::new(dir.clone())));
+        let password = Password::from("secret".to_string());
+
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server_with(intro(), peer::Heartbeat::default(), peer::Limits::default(), cancel.clone()).await;
+        let (mut r, mut w) = connect(addr).await;
+        let (_, token) = send_credentials_for_session(
+            client::IntroMsg::Register(username("Ig"), password.clone()), &mut w, &mut r).await.unwrap();
+        let code = enter_room(None, &mut w, &mut r).await.unwrap().unwrap();
+        start_game_alone(&mut w, &mut r).await;
+        sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+        server.await.unwrap().unwrap();
+        drop((r, w));
+
+        let snapshots = snapshot::Snapshots::new(dir.clone());
+        let mut game = snapshots.load_game(code).expect("A snapshot of the game");
+        edit(&mut game);
+        snapshots.save(game);
+        snapshots.flush().await;
+
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server_with(intro(), peer::Heartbeat::default(), peer::Limits::default(), cancel.clone()).await;
+        let (mut r, mut w) = connect(addr).await;
+        send_credentials(client::IntroMsg::Login(username("Ig"), password, token), &mut w, &mut r).await.unwrap();
+        send_all(&mut w, [client::IntroMsg::JoinRoom(code)]).await;
+        next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::IntroMsg::ReconnectGame(_)) => Some(()),
+            _ => None,
+        })
+        .await
+        .expect("The game must be restored");
+        RestoredGame { addr, dir, cancel, server, r, w }
+    }
+    // Ig attacks the monster line with the selected ability
+    fn select_for_attack(game: &mut snapshot::GameSnapshot, ability: Rank, line: &[Card]) {
+        game.phase = crate::protocol::GamePhaseKind::AttachMonster;
+        let (_, peer) = &mut game.players[0];
+        let deck = &mut peer.abilities;
+        if !deck.hand.contains(&Some(ability)) {
+            let old = deck.hand[0].replace(ability).expect("A full hand");
+            deck.draw_pile
+                .iter_mut()
+                .chain(deck.discard_pile.iter_mut())
+                .filter(|r| **r == ability)
+                .for_each(|r| *r = old);
+        }
+        peer.selected_ability = Some(ability);
+        game.monster_line.clear();
+        for card in line {
+            let i = game.monsters.cards.iter().position(|c| c == card).expect("A card of the deck");
+            game.monster_line.push(details::ActiveState::Enable(i));
+        }
+    }
+    async fn attack(game: &mut RestoredGame, monster: Card) -> Option<server::TurnResult<Result<Card, server::AttackError>>> {
+        send_all(&mut game.w, [client::GameMsg::Attack(monster)]).await;
+        next_matching(&mut game.r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::GameMsg::Attack(result)) => Some(result),
+            _ => None,
+        })
+        .await
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn close_the_room_after_the_game_is_over() {
+        let king = Card::new(Rank::King, Suit::Clubs);
+        let six = Card::new(Rank::Six, Suit::Hearts);
+        // the surviving king defeats the last player
+        let mut game = restore_edited_game("game-over", |g| {
+            select_for_attack(g, Rank::Six, &[king, six]);
+            g.players[0].1.health = 1;
+        })
+        .await;
+        send_all(&mut game.w, [client::GameMsg::Attack(six)]).await;
+        // the turn reply and the final board come before the logout
+        let (mut outcome, mut reply, mut board) = (None, false, false);
+        let logout = loop {
+            match game.r.next::<Msg<server::SharedMsg, server::GameMsg>>().await {
+                Some(Ok(Msg::State(server::GameMsg::GameOver { outcome: o, .. }))) => outcome = Some(o),
+                Some(Ok(Msg::State(server::GameMsg::Attack(_)))) => reply = true,
+                Some(Ok(Msg::State(server::GameMsg::UpdateGameData(_)))) => board = outcome.is_some(),
+                Some(Ok(Msg::Shared(server::SharedMsg::Logout))) => break true,
+                None => break false,
+                Some(_) => (),
+            }
+        };
+        let closed = game.r.next::<Msg<server::SharedMsg, server::GameMsg>>().await;
+        sleep(Duration::from_millis(100)).await;
+
+        let (mut r, mut w) = connect_as(game.addr, "Ks").await;
+        send_all(&mut w, [client::IntroMsg::GetRooms]).await;
+        let rooms = next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::IntroMsg::Rooms(rooms)) => Some(rooms),
+            _ => None,
+        })
+        .await;
+        let snapshots = snapshot::Snapshots::new(game.dir.clone()).load_games();
+        game.close().await;
+        assert!(matches!(outcome, Some(server::GameOutcome::Defeat)));
+        assert!(logout, "The players must be logged out");
+        assert!(reply, "The turn reply must not be lost");
+        assert!(board, "The final board must not be lost");
+        assert!(closed.is_none(), "The connection must be closed");
+        assert!(rooms.expect("Rooms were not received").is_empty(), "The room must be closed");
+        assert!(snapshots.is_empty(), "The snapshot must be removed");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn mage_hits_the_whole_line() {
+        let king = Card::new(Rank::King, Suit::Clubs);
+        let six = Card::new(Rank::Six, Suit::Hearts);
+        let mut game = restore_edited_game("whole-line", |g| {
+            g.players[0].1.abilities.suit = Suit::Spades;
+            select_for_attack(g, Rank::Ace, &[king, six]);
+        })
+        .await;
+        let hit = attack(&mut game, king).await;
+        let line = next_matching(&mut game.r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::GameMsg::UpdateGameData(data)) => Some(data.monsters),
+            _ => None,
+        })
+        .await;
+        game.close().await;
+        assert!(matches!(hit, Some(Ok(Ok(monster))) if monster == king));
+        // two hits of three for the king, one for the six
+        let line = line.expect("Data of the game");
+        assert!(line.contains(&Some(king)), "The king must survive");
+        assert!(!line.contains(&Some(six)), "The six must be defeated");
+    }
+    #[tokio::test]
+    async fn mage_hits_the_rest_of_the_line_after_a_gone_monster() {
+        let line = [
+            Card::new(Rank::Six, Suit::Hearts),
+            Card::new(Rank::Seven, Suit::Clubs),
+            Card::new(Rank::Eight, Suit::Spades),
+        ];
+        let gone = line[1];
+        let mut hit = Vec::new();
+        peer::hit_whole_line(line, |m| {
+            hit.push(m);
+            async move {
+                Ok(if m == gone { Err(details::DeactivateItemError::AlreadyNotActive) } else { Ok(()) })
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(hit, line, "Every monster must be hit");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn keep_the_turn_while_a_monster_falls_to_the_ability() {
+        let king = Card::new(Rank::King, Suit::Clubs);
+        let six = Card::new(Rank::Six, Suit::Hearts);
+        let mut game = restore_edited_game("too-weak", |g| select_for_attack(g, Rank::Six, &[king, six])).await;
+        let too_weak = attack(&mut game, king).await;
+        let hit = attack(&mut game, six).await;
+        game.close().await;
+        assert!(matches!(
+            too_weak,
+            Some(Ok(Err(server::AttackError::TooWeak { ability: Rank::Six, monster }))) if monster == king
+        ));
+        assert!(matches!(hit, Some(Ok(Ok(monster))) if monster == six), "The turn must stay");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn lose_the_turn_and_the_ability_without_a_target() {
+        let king = Card::new(Rank::King, Suit::Clubs);
+        let mut game = restore_edited_game("no-target", |g| select_for_attack(g, Rank::Six, &[king])).await;
+        let no_target = attack(&mut game, king).await;
+        let hand = next_matching(&mut game.r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::GameMsg::UpdateGameData(data)) => Some(data.abilities),
+            _ => None,
+        })
+        .await;
+        game.close().await;
+        assert!(matches!(
+            no_target,
+            Some(Ok(Err(server::AttackError::NoTarget { ability: Rank::Six })))
+        ));
+        assert!(!hand.expect("Data of the game").contains(&Some(Rank::Six)), "The ability must be discarded");
+    }
+    // a queen of the Mage swaps a too strong monster
+    fn mage_swaps(game: &mut snapshot::GameSnapshot, deck: &Deck, line: &[Card]) {
+        game.monsters = deck.clone();
+        game.players[0].1.abilities.suit = Suit::Spades;
+        select_for_attack(game, Rank::Queen, line);
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn swap_a_too_strong_monster_with_one_of_its_kind() {
+        let deck = Deck::new_monster_deck();
+        // kings are every ninth card
+        let (first_king, last_king) = (deck.cards[0], deck.cards[27]);
+        let six = *deck.cards.iter().find(|c| c.rank == Rank::Six).unwrap();
+
+        let mut game = restore_edited_game("swap", |g| mage_swaps(g, &deck, &[first_king])).await;
+        let swapped = attack(&mut game, first_king).await;
+        let line = next_matching(&mut game.r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::GameMsg::UpdateGameData(data)) => Some(data.monsters),
+            _ => None,
+        })
+        .await;
+        game.close().await;
+        assert!(matches!(swapped, Some(Ok(Ok(monster))) if monster == first_king));
+        let new = line.expect("Data of the game").into_iter().flatten().next().expect("A monster");
+        assert!(new.rank == Rank::King && new != first_king, "A king is swapped with {:?}", new);
+
+        let mut game = restore_edited_game("nothing-to-swap", |g| mage_swaps(g, &deck, &[last_king, six])).await;
+        let nothing = attack(&mut game, last_king).await;
+        let hit = attack(&mut game, six).await;
+        game.close().await;
+        assert!(matches!(
+            nothing,
+            Some(Ok(Err(server::AttackError::NothingToSwap { monster }))) if monster == last_king
+        ));
+        assert!(matches!(hit, Some(Ok(Ok(monster))) if monster == six), "The turn must stay");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn play_with_a_bot() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut r, mut w) = connect_as(addr, "Ig").await;
+        enter_room(None, &mut w, &mut r).await.unwrap().unwrap();
+        send_all(&mut w, [
+            client::HomeMsg::SetPlayerCount(2),
+            client::HomeMsg::AddBot,
+            client::HomeMsg::Ready(true),
+            client::HomeMsg::StartRoles,
+        ]).await;
+        next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::StartRoles(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+        send_all(&mut w, [client::RolesMsg::Select(crate::game::Role::Warrior)]).await;
+        // the bot takes a free role
+        next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::RolesMsg::AvailableRoles(roles))
+                if roles.iter().filter(|r| matches!(r, crate::protocol::RoleStatus::NotAvailable(_))).count() == 2 => Some(()),
+            _ => None,
+        })
+        .await
+        .expect("The bot has not selected a role");
+        send_all(&mut w, [client::RolesMsg::StartGame]).await;
+        let started = next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::RolesMsg::StartGame(game)) => Some(game),
+            _ => None,
+        })
+        .await
+        .expect("The game was not started");
+        // the next phase comes after both players drop an ability
+        let next_phase = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match r.next::<Msg<server::SharedMsg, server::GameMsg>>().await {
+                    Some(Ok(Msg::State(server::GameMsg::Turn(crate::protocol::TurnStatus::Ready(
+                        crate::protocol::GamePhaseKind::DropAbility,
+                    ))))) => {
+                        let rank = started.abilities[0].expect("A full hand");
+                        send_all(&mut w, [client::GameMsg::DropAbility(rank)]).await;
+                    }
+                    Some(Ok(Msg::State(server::GameMsg::Turn(crate::protocol::TurnStatus::Ready(
+                        phase,
+                    ))))) => break Some(phase),
+                    None => break None,
+                    _ => (),
+                }
+            }
+        })
+        .await;
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(matches!(
+            next_phase,
+            Ok(Some(crate::protocol::GamePhaseKind::SelectAbility))
+        ));
+    }
+    async fn start_rejected(r: &mut Reader) -> Option<server::StartRolesError> {
+        next_matching(r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::StartRejected(e)) => Some(e),
+            _ => None,
+        })
+        .await
+    }
+    async fn roster_locked(r: &mut Reader, locked: bool) {
+        next_matching(r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::Roster(roster)) if roster.locked == locked => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn host_controls_the_lobby() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut hr, mut hw) = connect_as(addr, "Ig").await;
+        let code = enter_room(None, &mut hw, &mut hr).await.unwrap().unwrap();
+        send_all(&mut hw, [client::HomeMsg::Lock(true)]).await;
+        roster_locked(&mut hr, true).await;
+
+        let (mut r, mut w) = connect_as(addr, "Ks").await;
+        let locked = enter_room(Some(code), &mut w, &mut r).await.unwrap();
+        send_all(&mut hw, [client::HomeMsg::Lock(false)]).await;
+        roster_locked(&mut hr, false).await;
+        enter_room(Some(code), &mut w, &mut r).await.unwrap().unwrap();
+        send_all(&mut w, [client::HomeMsg::StartRoles]).await;
+        let not_host = start_rejected(&mut r).await;
+        // the second player is not ready
+        send_all(&mut hw, [client::HomeMsg::Ready(true), client::HomeMsg::StartRoles]).await;
+        let not_ready = start_rejected(&mut hr).await;
+        let player_count = |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::PlayerCount(count)) => Some(count),
+            _ => None,
+        };
+        // a non-host gets the current count back
+        send_all(&mut w, [client::HomeMsg::SetPlayerCount(4)]).await;
+        let not_set = next_matching(&mut r, player_count).await;
+        send_all(&mut hw, [client::HomeMsg::SetPlayerCount(3)]).await;
+        let set = next_matching(&mut r, player_count).await;
+        send_all(&mut hw, [client::HomeMsg::Kick(username("Ks"))]).await;
+        let kicked = next_matching(&mut r, |msg| match msg {
+            Msg::<_, server::HomeMsg>::Shared(server::SharedMsg::Kicked) => Some(()),
+            _ => None,
+        })
+        .await;
+        shutdown(hw.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert_eq!(locked, Err(server::EnterRoomError::Locked));
+        assert_eq!(not_host, Some(server::StartRolesError::HostOnly));
+        assert_eq!(not_set, Some(2), "Only the host sets the player count");
+        assert_eq!(set, Some(3));
+        assert_eq!(not_ready, Some(server::StartRolesError::NotReady));
+        assert!(kicked.is_some(), "The kicked player must get a message");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn ignore_a_repeated_start() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut r, mut w) = connect_as(addr, "Ig").await;
+        enter_room(None, &mut w, &mut r).await.unwrap().unwrap();
+        send_all(&mut w, [
+            client::HomeMsg::SetPlayerCount(1),
+            client::HomeMsg::Ready(true),
+            client::HomeMsg::StartRoles,
+            client::HomeMsg::StartRoles,
+        ]).await;
+        let roles = next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::HomeMsg::StartRoles(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+        send_all(&mut w, [
+            client::RolesMsg::Select(crate::game::Role::Warrior),
+            client::RolesMsg::StartGame,
+            client::RolesMsg::StartGame,
+        ]).await;
+        let game = next_matching(&mut r, |msg| match msg {
+            Msg::<server::SharedMsg, _>::State(server::RolesMsg::StartGame(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+        w.send(encode_message(Msg::<client::SharedMsg, client::GameMsg>::with(client::SharedMsg::Ping)))
+            .await
+            .unwrap();
+        let alive = next_matching(&mut r, |msg| match msg {
+            Msg::<_, server::GameMsg>::Shared(server::SharedMsg::Pong) => Some(()),
+            _ => None,
+        })
+        .await;
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(roles.is_some() && game.is_some(), "The room must start once");
+        assert!(alive.is_some(), "The room must not crash");
+    }
+    #[traced_test]
+    #[tokio::test]
+    async fn reject_invalid_moves() {
+        let cancel = CancellationToken::new();
+        let (addr, server) = spawn_server(cancel.clone()).await;
+        let (mut r, mut w) = connect_as(addr, "Ig").await;
+        enter_room(None, &mut w, &mut r).await.unwrap().unwrap();
+        let started = start_game_alone(&mut w, &mut r).await;
+        let in_hand = started.abilities[0].expect("A full hand");
+        let not_in_hand = *crate::game::Rank::all()
+            .iter()
+            .find(|rank| !started.abilities.contains(&Some(**rank)))
+            .unwrap();
+        let monster = started.monsters[0].expect("A monster on the line");
+
+        let mut replies = Vec::new();
+        for msg in [
+            client::GameMsg::Attack(monster),
+            client::GameMsg::DropAbility(not_in_hand),
+            client::GameMsg::DropAbility(in_hand),
+        ] {
+            send_all(&mut w, [msg]).await;
+            replies.push(next_matching(&mut r, |msg| match msg {
+                Msg::<server::SharedMsg, _>::State(
+                    msg @ (server::GameMsg::MoveRejected(_) | server::GameMsg::DropAbility(_)),
+                ) => Some(msg),
+                _ => None,
+            }).await);
+        }
+        shutdown(w.get_mut(), cancel).await;
+        server.await.unwrap().unwrap();
+        assert!(matches!(
+            replies[0],
+            Some(server::GameMsg::MoveRejected(server::MoveRejection::WrongPhase(
+                crate::protocol::GamePhaseKind::DropAbility
+            )))
+        ));
+        assert!(matches!(
+            replies[1],
+            Some(server::GameMsg::MoveRejected(server::MoveRejection::NotInHand(rank))) if rank == not_in_hand
+        ));
+        // the connection survives rejected moves
+        assert!(matches!(
+            replies[2],
+            Some(server::GameMsg::DropAbility(Ok(rank))) if rank == in_hand
+        ));
+    }
+}
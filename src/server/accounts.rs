@@ -0,0 +1,157 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Context as _;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use tracing::{error, warn};
+
+use crate::protocol::{Password, Username};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AccountError {
+    #[error("Account already exists")]
+    AlreadyExists,
+    #[error("Unknown username or wrong password")]
+    BadCredentials,
+    #[error("Failed to hash a password = {0}")]
+    Hash(argon2::password_hash::Error),
+    #[error("Password hashing task failed = {0}")]
+    Task(tokio::task::JoinError),
+}
+
+// registered users with salted argon2 password hashes in the PHC format.
+// Without a file accounts live until the server stops
+#[derive(Default)]
+pub struct Accounts {
+    file: Option<PathBuf>,
+    users: HashMap<String, String>,
+}
+
+impl Accounts {
+    // an absent file is a new empty store
+    pub fn load(file: PathBuf) -> anyhow::Result<Self> {
+        let users = match std::fs::read_to_string(&file) {
+            Ok(data) => serde_json::from_str(&data)
+                .with_context(|| format!("Invalid accounts file {}", file.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::default(),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read the accounts file {}", file.display())
+                })
+            }
+        };
+        Ok(Accounts {
+            file: Some(file),
+            users,
+        })
+    }
+
+    pub fn password_hash(&self, username: &Username) -> Option<String> {
+        self.users.get(&**username).cloned()
+    }
+
+    // the hash is made by hash_password outside of the store
+    pub fn add(&mut self, username: &Username, hash: String) -> Result<(), AccountError> {
+        if self.users.contains_key(&**username) {
+            return Err(AccountError::AlreadyExists);
+        }
+        self.users.insert(username.to_string(), hash);
+        if let Err(e) = self.save() {
+            // the account still works until restart
+            error!(cause = %e, "Failed to save accounts");
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(file) = &self.file {
+            std::fs::write(file, serde_json::to_string_pretty(&self.users)?)
+                .with_context(|| format!("Failed to write {}", file.display()))?;
+        }
+        Ok(())
+    }
+}
+
+// argon2 is slow by design, keep it off the async workers
+// and out of the actor that owns the store
+pub async fn hash_password(password: Password) -> Result<String, AccountError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut rand::rngs::OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(AccountError::Task)?
+    .map_err(AccountError::Hash)
+}
+
+pub async fn verify_password(hash: String, password: Password) -> Result<(), AccountError> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&hash).map_err(|e| {
+            warn!(cause = %e, "Corrupted password hash");
+            AccountError::BadCredentials
+        })?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .map_err(|_| AccountError::BadCredentials)
+    })
+    .await
+    .map_err(AccountError::Task)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(s: &str) -> Username {
+        Username::new(arraystring::ArrayString::try_from_str(s).unwrap()).unwrap()
+    }
+    fn password(s: &str) -> Password {
+        Password::from(s.to_string())
+    }
+    async fn register(accounts: &mut Accounts, user: &str, pass: &str) -> Result<(), AccountError> {
+        accounts.add(&name(user), hash_password(password(pass)).await?)
+    }
+    async fn verify(accounts: &Accounts, user: &str, pass: &str) -> Result<(), AccountError> {
+        let hash = accounts
+            .password_hash(&name(user))
+            .ok_or(AccountError::BadCredentials)?;
+        verify_password(hash, password(pass)).await
+    }
+
+    #[tokio::test]
+    async fn register_and_verify() {
+        let mut accounts = Accounts::default();
+        register(&mut accounts, "Ig", "secret").await.unwrap();
+        assert!(matches!(
+            register(&mut accounts, "Ig", "other").await,
+            Err(AccountError::AlreadyExists)
+        ));
+        assert!(verify(&accounts, "Ig", "secret").await.is_ok());
+        assert!(matches!(
+            verify(&accounts, "Ig", "wrong").await,
+            Err(AccountError::BadCredentials)
+        ));
+        assert!(matches!(
+            verify(&accounts, "Ks", "secret").await,
+            Err(AccountError::BadCredentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn keep_accounts_in_file() {
+        let file =
+            std::env::temp_dir().join(format!("kobuleti-accounts-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let mut accounts = Accounts::load(file.clone()).unwrap();
+        register(&mut accounts, "Ig", "secret").await.unwrap();
+        let data = std::fs::read_to_string(&file).unwrap();
+        assert!(!data.contains("secret"), "Passwords must be hashed");
+        let accounts = Accounts::load(file.clone()).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(verify(&accounts, "Ig", "secret").await.is_ok());
+    }
+}
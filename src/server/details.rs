@@ -1,19 +1,72 @@
+use std::{sync::OnceLock, time::Duration};
+
+use arrayvec::ArrayVec;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorError {
+    // an actor has stopped or dropped the answer,
+    // its peer is usually disconnected at this moment
+    #[error("Actor is gone")]
+    Gone,
+    // an actor is stalled, e.g. blocked on a full channel or waits for the caller
+    #[error("Actor has not answered `{0}` in time")]
+    Timeout(&'static str),
+}
+
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+// calls longer than this are logged
+const SLOW_CALL: Duration = Duration::from_millis(100);
+
+static CALL_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+// replace the default deadline of actor calls, must be called before the server starts
+pub fn set_call_timeout(timeout: Duration) -> anyhow::Result<()> {
+    CALL_TIMEOUT
+        .set(timeout)
+        .map_err(|_| anyhow::anyhow!("Call timeout is already set"))
+}
+
+pub fn call_timeout() -> Duration {
+    *CALL_TIMEOUT.get_or_init(|| DEFAULT_CALL_TIMEOUT)
+}
+
 #[inline]
 pub async fn send_oneshot_and_wait<Cmd, F, R>(
     tx: &super::Tx<Cmd>,
+    cmd: &'static str,
+    timeout: Duration,
     cmd_factory: F,
-) -> Result<R, tokio::sync::oneshot::error::RecvError>
+) -> Result<R, ActorError>
 where
     F: FnOnce(tokio::sync::oneshot::Sender<R>) -> Cmd,
 {
+    let start = tokio::time::Instant::now();
     let (one_tx, rx) = tokio::sync::oneshot::channel::<R>();
-    tx.send(cmd_factory(one_tx)).await.expect("Open");
-    rx.await
+    let answer = tokio::time::timeout(timeout, async {
+        tx.send(cmd_factory(one_tx))
+            .await
+            .map_err(|_| ActorError::Gone)?;
+        rx.await.map_err(|_| ActorError::Gone)
+    })
+    .await;
+    let elapsed = start.elapsed();
+    match answer {
+        Ok(answer) => {
+            if elapsed > SLOW_CALL {
+                tracing::warn!(cmd, ?elapsed, "Slow actor call");
+            }
+            answer
+        }
+        Err(_) => {
+            tracing::error!(cmd, ?elapsed, "Actor call timed out");
+            Err(ActorError::Timeout(cmd))
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ActiveState {
     Enable(usize),
     Disable(usize),
@@ -32,13 +85,13 @@ pub trait StatebleItem {
 }
 
 #[derive(Debug)]
-pub struct Stateble<A, const ACTIVE_COUNT: usize>
+pub struct Stateble<A, const MAX_ACTIVE_COUNT: usize>
 where
     A: AsRef<[<A as StatebleItem>::Item]> + StatebleItem,
     <A as StatebleItem>::Item: PartialEq + Eq,
 {
     pub items: A,
-    pub actives: [ActiveState; ACTIVE_COUNT],
+    pub actives: ArrayVec<ActiveState, MAX_ACTIVE_COUNT>,
 }
 
 #[derive(Error, Debug)]
@@ -53,23 +106,30 @@ pub enum DeactivateItemError {
     AlreadyNotActive,
 }
 
-impl<A, const ACTIVE_COUNT: usize> Stateble<A, ACTIVE_COUNT>
+impl<A, const MAX_ACTIVE_COUNT: usize> Stateble<A, MAX_ACTIVE_COUNT>
 where
     A: AsRef<[<A as StatebleItem>::Item]> + StatebleItem,
     <A as StatebleItem>::Item: PartialEq + Eq,
 {
     pub fn with_items(items: A) -> Self {
-        Stateble::<A, ACTIVE_COUNT> {
+        Stateble::with_active_count(items, MAX_ACTIVE_COUNT)
+    }
+    pub fn with_active_count(items: A, count: usize) -> Self {
+        assert!(count <= MAX_ACTIVE_COUNT, "Too many active items");
+        Stateble::<A, MAX_ACTIVE_COUNT> {
             items,
-            actives: core::array::from_fn(ActiveState::Enable),
+            actives: (0..count).map(ActiveState::Enable).collect(),
         }
     }
 
-    pub fn active_items(&self) -> [Option<&<A as StatebleItem>::Item>; ACTIVE_COUNT] {
-        self.actives.map(|s| match s {
-            ActiveState::Enable(s) => Some(&self.items.as_ref()[s]),
-            ActiveState::Disable(_) => None,
-        })
+    pub fn active_items(&self) -> ArrayVec<Option<&<A as StatebleItem>::Item>, MAX_ACTIVE_COUNT> {
+        self.actives
+            .iter()
+            .map(|s| match *s {
+                ActiveState::Enable(s) => Some(&self.items.as_ref()[s]),
+                ActiveState::Disable(_) => None,
+            })
+            .collect()
     }
     pub fn deactivate_item_by_index(&mut self, i: usize) -> Result<(), DeactivateItemError> {
         *self
@@ -99,9 +159,10 @@ where
     }
 
     pub fn next_actives(&mut self) -> Result<(), EndOfItems> {
+        let count = self.actives.len();
         for (i, a) in self.actives.iter_mut().enumerate() {
             let new_index = if let ActiveState::Disable(d) = a {
-                *d + ACTIVE_COUNT
+                *d + count
             } else {
                 a.unwrap_index()
             };
@@ -115,13 +176,14 @@ where
     }
 
     pub fn repeat_after_eof(&mut self, eof: EndOfItems) {
-        for i in 0..(ACTIVE_COUNT - eof.0) {
+        for i in 0..(self.actives.len() - eof.0) {
             self.actives[i] = ActiveState::Enable(i);
         }
     }
     #[allow(dead_code)]
     pub fn reset(&mut self) {
-        self.actives = core::array::from_fn(ActiveState::Enable);
+        let count = self.actives.len();
+        self.actives = (0..count).map(ActiveState::Enable).collect();
     }
 }
 
@@ -158,7 +220,7 @@ macro_rules! actor_api {
     };
 
     (@impl_enum $cmd:ident {
-        $($vis:vis $(async)? fn $fname:ident(&self $(,$_:ident : $ty:ty)*) $( -> Result<$ret:ty, RecvError> )?;)*
+        $($vis:vis $(async)? fn $fname:ident(&self $(,$_:ident : $ty:ty)*) $( -> Result<$ret:ty, ActorError> )?;)*
 
     }) => {
 
@@ -185,7 +247,10 @@ macro_rules! actor_api {
         paste::item! {
             #[inline]
             $vis async fn $fname(&self, $($vname: $type,)*){
-                self.tx.send(<Msg<_, _> as crate::protocol::With<_, _>>::with($cmd::[<$fname:camel>]($($vname, )*))).await.expect("Open");
+                // nobody waits for an answer, a message to a stopped actor is lost
+                if self.tx.send(<Msg<_, _> as crate::protocol::With<_, _>>::with($cmd::[<$fname:camel>]($($vname, )*))).await.is_err() {
+                    tracing::debug!(cmd = stringify!($fname), "Actor is gone");
+                }
             }
 
         }
@@ -201,7 +266,7 @@ macro_rules! actor_api {
             #[must_use = "Unused api responce"]
             #[inline]
             $vis async fn $fname(&self, $($vname: $type,)*) -> $ret {
-                crate::server::details::send_oneshot_and_wait(&self.tx, |tx| <Msg<_, _> as crate::protocol::With<_, _>>::with($cmd::[<$fname:camel>]($($vname, )* tx))).await
+                crate::server::details::send_oneshot_and_wait(&self.tx, stringify!($fname), crate::server::details::call_timeout(), |tx| <Msg<_, _> as crate::protocol::With<_, _>>::with($cmd::[<$fname:camel>]($($vname, )* tx))).await
             }
 
         }
@@ -212,3 +277,55 @@ macro_rules! actor_api {
 
 }
 pub(crate) use actor_api;
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn call_a_stopped_actor() {
+        let (tx, rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+        drop(rx);
+        assert_eq!(
+            send_oneshot_and_wait(&tx, "test", TIMEOUT, |tx| tx).await,
+            Err(ActorError::Gone)
+        );
+
+        // the actor takes the message but drops the answer
+        let (tx, mut rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+        tokio::spawn(async move { drop(rx.recv().await) });
+        assert_eq!(
+            send_oneshot_and_wait(&tx, "test", TIMEOUT, |tx| tx).await,
+            Err(ActorError::Gone)
+        );
+    }
+
+    #[tokio::test]
+    async fn call_a_stalled_actor() {
+        // the actor holds the answer and never replies
+        let (tx, mut rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+        let actor = tokio::spawn(async move {
+            let answer = rx.recv().await;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            drop(answer);
+        });
+        assert_eq!(
+            send_oneshot_and_wait(&tx, "test", TIMEOUT, |tx| tx).await,
+            Err(ActorError::Timeout("test"))
+        );
+        actor.abort();
+
+        // a full queue of the actor
+        let (tx, _rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+        let (busy, _) = oneshot::channel();
+        tx.send(busy).await.unwrap();
+        assert_eq!(
+            send_oneshot_and_wait(&tx, "test", TIMEOUT, |tx| tx).await,
+            Err(ActorError::Timeout("test"))
+        );
+    }
+}
@@ -1,33 +1,42 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use anyhow::Context as _;
 use futures::SinkExt;
+use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpStream,
-    sync::{
-        mpsc::channel,
-        oneshot::{self, error::RecvError},
-    },
+    sync::{mpsc::channel, oneshot},
+    time::{interval_at, timeout, Instant, MissedTickBehavior},
 };
-use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
-use super::{details::actor_api, states, Answer, Handle, Tx, MPSC_CHANNEL_CAPACITY};
+use super::{
+    accounts::{self, AccountError},
+    details::{actor_api, ActorError},
+    states, Answer, Handle, Rx, Tx, MPSC_CHANNEL_CAPACITY,
+};
 use crate::{
-    game::{AbilityDeck, Card, Deckable, Rank, Role, Suit},
+    game::{
+        effects::{self, Effect},
+        AbilityDeck, Card, Deckable, Rank, Role, Suit,
+    },
     protocol::{
-        client, encode_message, server::ABILITY_COUNT, AsyncMessageReceiver, MessageDecoder, Msg,
-        Username,
+        client,
+        codec::{self, Format, FrameCodec},
+        encode_message,
+        server::{AttackError, MoveRejection, RoomCode, MAX_HEALTH},
+        AsyncMessageReceiver, GamePhaseKind, Hello, MessageDecoder, Msg, Password, SessionToken,
+        Username, VersionMismatch, Welcome, PROTOCOL_VERSION,
     },
-    server::details::Stateble,
 };
 
 pub type PeerHandle<T> = Handle<Msg<self::SharedCmd, T>>;
 
 actor_api! { // Shared
     impl<M>  Handle<Msg<SharedCmd, M>>{
-        pub async fn ping(&self) -> Result<(), RecvError>;
-        pub async fn get_username(&self) -> Result<Username, RecvError>;
+        pub async fn ping(&self) -> Result<(), ActorError>;
+        pub async fn get_username(&self) -> Result<Username, ActorError>;
         pub async fn close(&self);
 
     }
@@ -36,28 +45,29 @@ actor_api! { // Shared
 actor_api! { // Intro
     impl  Handle<Msg<SharedCmd, IntroCmd>>{
         pub async fn set_username(&self, username: Username);
+        pub async fn fail_login(&self) -> Result<u32, ActorError>;
         pub async fn send_tcp(&self, msg: Msg<server::SharedMsg, server::IntroMsg>);
-        pub async fn enter_game(&self, server: states::HomeHandle) -> Result<HomeHandle, RecvError>;
-        pub async fn reconnect_roles(&self, server: states::RolesHandle, old_peer: RolesHandle) -> Result<RolesHandle, RecvError>;
-        pub async fn reconnect_game(&self, server: states::GameHandle, old_peer: GameHandle) -> Result<GameHandle, RecvError>;
+        pub async fn enter_game(&self, server: states::HomeHandle, room: RoomCode) -> Result<HomeHandle, ActorError>;
+        pub async fn reconnect_roles(&self, server: states::RolesHandle, old_peer: RolesHandle) -> Result<RolesHandle, ActorError>;
+        pub async fn reconnect_game(&self, server: states::GameHandle, old_peer: GameHandle) -> Result<GameHandle, ActorError>;
     }
 }
 
 actor_api! { // Home
     impl  Handle<Msg<SharedCmd, HomeCmd>>{
         pub async fn send_tcp(&self, msg: Msg<server::SharedMsg, server::HomeMsg>);
-        pub async fn start_roles(&self, server_handle: states::RolesHandle) -> Result<RolesHandle, RecvError>;
+        pub async fn start_roles(&self, server_handle: states::RolesHandle) -> Result<RolesHandle, ActorError>;
 
 
     }
 }
 actor_api! { // Roles
     impl  Handle<Msg<SharedCmd, RolesCmd>>{
-        pub async fn take_peer(&self) ->  Result<Peer<Roles>, RecvError>;
+        pub async fn take_peer(&self) ->  Result<Peer<Roles>, ActorError>;
         pub async fn send_tcp(&self, msg: Msg<server::SharedMsg, server::RolesMsg>)   ;
-        pub async fn start_game(&self, server_handle: states::GameHandle) ->  Result<GameHandle, RecvError>;
-        pub async fn get_role(&self) ->  Result<Option<Role>, RecvError>;
-        pub async fn select_role(&self, role: Role) ->  Result<(), RecvError>;
+        pub async fn start_game(&self, server_handle: states::GameHandle) ->  Result<GameHandle, ActorError>;
+        pub async fn get_role(&self) ->  Result<Option<Role>, ActorError>;
+        pub async fn select_role(&self, role: Role) ->  Result<(), ActorError>;
 
 
     }
@@ -65,14 +75,17 @@ actor_api! { // Roles
 
 actor_api! { // Game
     impl  Handle<Msg<SharedCmd, GameCmd>>{
-        pub async fn take_peer(&self) -> Result<Peer<Game>, RecvError>;
+        pub async fn take_peer(&self) -> Result<Peer<Game>, ActorError>;
         pub async fn send_tcp(&self, msg: Msg<server::SharedMsg, server::GameMsg>)   ;
-        pub async fn get_abilities(&self) -> Result<[Option<Rank>; 3], RecvError>;
-        pub async fn select_ability(&self, ability: Rank) ->  Result<(), RecvError>;
-        pub async fn drop_ability(&self, ability: Rank) ->  Result<(), RecvError>;
-        pub async fn attack(&self, monster: Card) ->  Result<(), RecvError>;
-        pub async fn continue_game(&self) ->  Result<(), RecvError>;
+        pub async fn get_abilities(&self) -> Result<[Option<Rank>; 3], ActorError>;
+        pub async fn get_selected_ability(&self) -> Result<Option<Rank>, ActorError>;
+        pub async fn select_ability(&self, ability: Rank) ->  Result<(), ActorError>;
+        pub async fn drop_ability(&self, ability: Rank) ->  Result<(), ActorError>;
+        pub async fn attack(&self, monster: Card) ->  Result<Result<(), AttackError>, ActorError>;
+        pub async fn continue_game(&self) ->  Result<(), ActorError>;
         pub async fn sync_with_client(&self);
+        pub async fn defend(&self, monster: Option<Card>, shield: u16);
+        pub async fn leave_game(&self);
 
     }
 }
@@ -130,6 +143,9 @@ where
 #[derive(Default)]
 pub struct Intro {
     username: Option<Username>,
+    room: Option<RoomCode>,
+    // every attempt runs argon2, a connection has only a few
+    failed_logins: u32,
 }
 impl Intro {
     fn get_username(&self) -> &Username {
@@ -146,23 +162,33 @@ pub struct Roles {
     pub selected_role: Option<Role>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Game {
-    pub abilities: Stateble<AbilityDeck, ABILITY_COUNT>,
-    pub selected_ability: Option<usize>,
+    pub abilities: AbilityDeck,
+    pub selected_ability: Option<Rank>,
     pub health: u16,
+    pub skip_monster_attack: bool,
 }
 impl Game {
     pub fn new(role: Suit) -> Self {
         let mut abilities = AbilityDeck::new(role);
         abilities.shuffle();
+        abilities.draw();
         Game {
-            abilities: Stateble::with_items(abilities),
-            health: 36,
+            abilities,
+            health: MAX_HEALTH,
             selected_ability: None,
+            skip_monster_attack: false,
         }
     }
     pub fn get_role(&self) -> Suit {
-        self.abilities.items.suit
+        self.abilities.suit
+    }
+    pub fn piles(&self) -> server::PileSizes {
+        server::PileSizes {
+            draw: self.abilities.draw_pile.len() as u8,
+            discard: self.abilities.discard_pile.len() as u8,
+        }
     }
 }
 
@@ -371,6 +397,14 @@ where
     pub fn close_socket(&mut self) {
         self.socket = None;
     }
+    // a game goes on while the peer is offline
+    async fn send_tcp(&mut self, msg: <Handle<M> as SendSocketMessage>::Msg) {
+        if let Some(s) = self.socket.as_ref() {
+            if s.send(msg).await.is_err() {
+                self.close_socket();
+            }
+        }
+    }
 }
 
 macro_rules! done {
@@ -384,78 +418,291 @@ macro_rules! done {
 
 struct NotifyServer<State, PeerHandle>(pub State, pub Answer<PeerHandle>);
 
-#[tracing::instrument(skip_all, name="Peer", fields(p = %socket.peer_addr().unwrap()))]
-pub async fn accept_connection(
-    socket: &mut TcpStream,
-    intro_server: states::IntroHandle,
-) -> anyhow::Result<()> {
-    let addr = socket.peer_addr()?;
-    macro_rules! run_state {
-        ($visitor:expr, $connection:expr, $peer_rx:expr ) => {
-            async {
-                let (done, mut done_rx) = oneshot::channel();
-                let mut state = ReduceState {
-                    connection: $connection,
-                    done: Some(done),
-                };
-                loop {
-                    tokio::select! {
-                        new_state = &mut done_rx => {
-                            return Ok::<Option<_>, anyhow::Error>(Some(($visitor, new_state?)))
-                        }
-                        cmd = $peer_rx.recv() => match cmd {
-                            Some(peer_cmd) => {
-                                debug!(?peer_cmd);
-                                match peer_cmd {
-                                    Msg::Shared(cmd) =>  match cmd {
-                                        SharedCmd::Ping(tx) => {
-                                             tx.send(()).unwrap();
-                                        }
-                                        SharedCmd::Close() => {
-                                                state.connection.close_socket();
-                                                break;
+macro_rules! run_state {
+    ($visitor:expr, $connection:expr, $peer_rx:expr, $addr:expr ) => {
+        async {
+            let (done, mut done_rx) = oneshot::channel();
+            let mut state = ReduceState {
+                connection: $connection,
+                done: Some(done),
+            };
+            loop {
+                tokio::select! {
+                    new_state = &mut done_rx => {
+                        return Ok::<Option<_>, anyhow::Error>(Some(($visitor, new_state?)))
+                    }
+                    cmd = $peer_rx.recv() => match cmd {
+                        Some(peer_cmd) => {
+                            debug!(?peer_cmd);
+                            match peer_cmd {
+                                Msg::Shared(cmd) =>  match cmd {
+                                    SharedCmd::Ping(tx) => {
+                                         let _ = tx.send(());
+                                    }
+                                    SharedCmd::Close() => {
+                                            state.connection.close_socket();
+                                            break;
 
-                                        }
-                                        SharedCmd::GetUsername(tx) => {
-                                            let _ = tx.send($visitor.get_username().clone());
-                                        }
+                                    }
+                                    SharedCmd::GetUsername(tx) => {
+                                        let _ = tx.send($visitor.get_username().clone());
+                                    }
 
-                                    },
-                                    Msg::State(cmd) => {
-                                        if let Err(e) = $visitor.reduce(cmd, &mut state).await {
-                                            error!(cause = %e, "Failed to process");
-                                            break;
-                                        }
+                                },
+                                Msg::State(cmd) => {
+                                    if let Err(e) = $visitor.reduce(cmd, &mut state).await {
+                                        error!(cause = %e, "Failed to process");
+                                        break;
                                     }
                                 }
                             }
-                            None => {
-                                // EOF. The last PeerHandle has been dropped
-                                info!(addr = ?state.connection.addr, "Drop peer actor");
-                                break
-                            }
+                        }
+                        None => {
+                            // EOF. The last PeerHandle has been dropped
+                            info!(addr = ?state.connection.addr, "Drop peer actor");
+                            break
                         }
                     }
                 }
-                Ok(None)
             }
-            .instrument(tracing::info_span!("PeerActor", ?addr))
-        };
+            Ok(None)
+        }
+        .instrument(tracing::info_span!("PeerActor", addr = ?$addr))
+    };
+}
+
+// bots spawn their peer actors by run_state!
+pub mod bot;
+
+// a peer of a game restored from a snapshot, without a connection until
+// the player logs in again and takes it
+pub fn restore_game(
+    addr: SocketAddr,
+    mut peer: Peer<Game>,
+    server: states::GameHandle,
+) -> GameHandle {
+    let (to_peer, mut peer_rx) = channel(MPSC_CHANNEL_CAPACITY);
+    let connection = Connection {
+        addr,
+        server,
+        socket: None,
+    };
+    tokio::spawn(async move {
+        if let Err(e) = run_state!(peer, connection, peer_rx, addr).await {
+            error!(cause = %e, "Restored peer error");
+        }
+    });
+    Handle::for_tx(to_peer)
+}
+
+// the server pings clients to find half-open connections
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    // unanswered pings in a row before the peer is dropped
+    pub max_missed: u32,
+}
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(5),
+            max_missed: 3,
+        }
     }
+}
+impl Heartbeat {
+    // the same silence as for a peer that misses its pings
+    pub fn timeout(&self) -> Duration {
+        self.interval * self.max_missed
+    }
+}
+
+// a client can not exhaust the memory of the server or flood it with garbage
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // bytes of a frame from a client, longer frames are skipped unread
+    pub max_frame: usize,
+    // chars of a chat message, the rest is cut
+    pub max_chat: usize,
+    // chat messages of a connection in CHAT_WINDOW, more are dropped as bad frames
+    pub max_chat_lines: u32,
+    // malformed or oversized frames of a connection before the peer is dropped
+    pub max_bad_frames: u32,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_frame: codec::DEFAULT_MAX_FRAME,
+            max_chat: 512,
+            max_chat_lines: 10,
+            max_bad_frames: 5,
+        }
+    }
+}
+
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+// failed logins of a connection before it is closed
+const MAX_LOGIN_ATTEMPTS: u32 = 5;
+
+// chat messages of a connection in the current window
+struct ChatRate {
+    window: Instant,
+    lines: u32,
+}
+impl ChatRate {
+    fn new() -> Self {
+        ChatRate {
+            window: Instant::now(),
+            lines: 0,
+        }
+    }
+    fn allow(&mut self, max_lines: u32) -> bool {
+        if self.window.elapsed() >= CHAT_WINDOW {
+            self.window = Instant::now();
+            self.lines = 0;
+        }
+        self.lines += 1;
+        self.lines <= max_lines
+    }
+}
+
+// client messages with a text of the chat
+trait ChatText {
+    fn chat_text(&mut self) -> Option<&mut String> {
+        None
+    }
+}
+impl ChatText for client::IntroMsg {}
+impl ChatText for client::HomeMsg {
+    fn chat_text(&mut self) -> Option<&mut String> {
+        match self {
+            client::HomeMsg::Chat(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+impl ChatText for client::RolesMsg {
+    fn chat_text(&mut self) -> Option<&mut String> {
+        match self {
+            client::RolesMsg::Chat(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+impl ChatText for client::GameMsg {
+    fn chat_text(&mut self) -> Option<&mut String> {
+        match self {
+            client::GameMsg::Chat(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+// a shared message in the message type of the current state of a socket
+fn shared_msg_of<M>(
+    _: &Rx<Msg<server::SharedMsg, M>>,
+    msg: server::SharedMsg,
+) -> Msg<server::SharedMsg, M> {
+    Msg::Shared(msg)
+}
+fn is_chat<M: ChatText>(msg: &mut M) -> bool {
+    msg.chat_text().is_some()
+}
+
+fn cut_chat<M: ChatText>(msg: &mut M, max_chars: usize) {
+    if let Some(text) = msg.chat_text() {
+        if let Some((end, _)) = text.char_indices().nth(max_chars) {
+            warn!(
+                bytes = text.len(),
+                max_chars, "Chat message is too long, cut it"
+            );
+            text.truncate(end);
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, name="Peer", fields(p = %socket.peer_addr().unwrap()))]
+pub async fn accept_connection(
+    socket: &mut TcpStream,
+    intro_server: states::IntroHandle,
+    heartbeat: Heartbeat,
+    limits: Limits,
+) -> anyhow::Result<()> {
+    let addr = socket.peer_addr()?;
     let (r, w) = socket.split();
-    let mut writer = FramedWrite::new(w, LinesCodec::new());
-    let mut reader = MessageDecoder::new(FramedRead::new(r, LinesCodec::new()));
+    let mut writer = FramedWrite::new(w, FrameCodec::new());
+    let mut reader = MessageDecoder::new(FramedRead::new(
+        r,
+        FrameCodec::with_max_frame(limits.max_frame),
+    ));
+    // for the whole connection, a new state does not forgive them
+    let mut bad_frames = 0;
+    let mut chat_rate = ChatRate::new();
+
+    // a client of another protocol version is rejected before any Msg,
+    // a silent one must not hold the socket forever
+    let hello = timeout(heartbeat.timeout(), reader.next::<Hello>())
+        .await
+        .context("No Hello in time")?
+        .context("Connection closed before Hello")?
+        .context("Expected Hello")?;
+    let server_hello = Hello::default();
+    let format = Format::negotiate(&hello.capabilities, &server_hello.capabilities);
+    info!(version = hello.protocol_version, app = %hello.app_version, ?format, "Hello");
+    if hello.protocol_version != PROTOCOL_VERSION {
+        let mismatch = VersionMismatch {
+            server: PROTOCOL_VERSION,
+            client: hello.protocol_version,
+            server_app: Hello::default().app_version,
+        };
+        warn!(%mismatch, "Reject the client");
+        writer
+            .send(encode_message(Welcome::Err(mismatch)))
+            .await
+            .context("Failed to send to the socket")?;
+        return Ok(());
+    }
+    writer
+        .send(encode_message(Welcome::Ok(server_hello)))
+        .await
+        .context("Failed to send to the socket")?;
+    // the next frames are in the agreed format
+    writer.encoder_mut().set_format(format);
+    reader.set_format(format);
 
     macro_rules! run_state_handle {
         ($handle:expr, $connection:expr, $socket:expr ) => {
             async {
+                let mut heartbeat_timer =
+                    interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+                heartbeat_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                // the first unanswered ping
+                let mut ping: Option<Instant> = None;
+                let mut missed = 0;
                 loop {
                     tokio::select! {
+                        _ = heartbeat_timer.tick() => {
+                            if ping.is_some() {
+                                missed += 1;
+                                if missed >= heartbeat.max_missed {
+                                    warn!(missed, "Client does not answer, drop the peer");
+                                    break;
+                                }
+                            }
+                            writer.send(encode_message(shared_msg_of(&$socket, server::SharedMsg::Ping)))
+                                .await.context("Failed to send to the socket")?;
+                            ping.get_or_insert_with(Instant::now);
+                        },
                         msg = $socket.recv() => match msg {
                             Some(tcp_msg) => {
                                debug!(?tcp_msg);
+                               let last = matches!(&tcp_msg, Msg::Shared(server::SharedMsg::ServerShutdown { .. } | server::SharedMsg::Kicked | server::SharedMsg::Logout));
                                writer.send(encode_message(tcp_msg)).await
                                     .context("Failed to send to the socket")?;
+                               // all queued messages are flushed before it
+                               if last {
+                                   break;
+                               }
                             }
                             None => {
                                 info!("Socket rx EOF");
@@ -463,9 +710,30 @@ pub async fn accept_connection(
                             }
                         },
 
+                        _ = $connection.server.tx.closed() => {
+                            // the supervisor of the room recovers it without this connection
+                            warn!("State server has stopped");
+                            writer.send(encode_message(Msg::<server::SharedMsg, server::IntroMsg>::Shared(
+                                server::SharedMsg::RoomCrashed,
+                            ))).await?;
+                            break;
+                        },
                         msg = reader.next::<Msg<client::SharedMsg, _>>() => match msg {
                             Some(client_msg) => {
-                                let client_msg = client_msg?;
+                                let client_msg = match client_msg {
+                                    Ok(msg) => msg,
+                                    // a bad frame is skipped, the stream goes on
+                                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                                        bad_frames += 1;
+                                        warn!(cause = %e, bad_frames, "Bad frame from the client");
+                                        if bad_frames >= limits.max_bad_frames {
+                                            warn!("Too many bad frames, drop the peer");
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                    Err(e) => return Err(e.into()),
+                                };
                                 debug!(?client_msg);
                                 match client_msg {
                                     Msg::Shared(msg) => match msg {
@@ -476,6 +744,12 @@ pub async fn accept_connection(
                                                 .as_ref().unwrap()
                                                 .send(Msg::with(server::SharedMsg::Pong)).await?;
                                         }
+                                        client::SharedMsg::Pong => {
+                                            if let Some(sent) = ping.take() {
+                                                debug!(rtt = ?sent.elapsed(), "Heartbeat");
+                                            }
+                                            missed = 0;
+                                        }
                                         client::SharedMsg::Logout => {
                                             let _ = $connection
                                                     .socket
@@ -484,7 +758,17 @@ pub async fn accept_connection(
                                                 break;
                                         }
                                     }
-                                    Msg::State(msg) => {
+                                    Msg::State(mut msg) => {
+                                        if is_chat(&mut msg) && !chat_rate.allow(limits.max_chat_lines) {
+                                            bad_frames += 1;
+                                            warn!(bad_frames, "Chat flood from the client, drop the message");
+                                            if bad_frames >= limits.max_bad_frames {
+                                                warn!("Too many bad frames, drop the peer");
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        cut_chat(&mut msg, limits.max_chat);
                                         $handle.reduce(
                                             msg,
                                            &mut $connection).await?;
@@ -516,7 +800,7 @@ pub async fn accept_connection(
                 let peer = tokio::spawn({
                     let connection = connection.clone();
                     async move {
-                        return run_state!(visitor, connection, peer_rx).await;
+                        return run_state!(visitor, connection, peer_rx, addr).await;
                     }
                 });
                 tokio::select!{
@@ -576,18 +860,17 @@ pub async fn accept_connection(
                                         Msg::<server::SharedMsg, server::RolesMsg>::State(
                                             server::RolesMsg::StartGame(
                                                 crate::protocol::client::StartGame {
-                                                    abilities: game
-                                                        .state
-                                                        .abilities
-                                                        .active_items()
-                                                        .map(|i| i.copied()),
+                                                    abilities: game.state.abilities.hand,
                                                     monsters: server.get_monsters().await?,
                                                     role: game.state.get_role(),
+                                                    health: game.state.health,
+                                                    piles: game.state.piles(),
                                                 },
                                             ),
                                         ),
                                     ))
                                     .await?;
+                                server.sync_player(addr, game.state.clone()).await;
                             },
                             game,
                             server,
@@ -607,13 +890,11 @@ pub async fn accept_connection(
                                         Msg::<server::SharedMsg, server::IntroMsg>::State(
                                             server::IntroMsg::ReconnectGame(
                                                 crate::protocol::client::StartGame {
-                                                    abilities: game
-                                                        .state
-                                                        .abilities
-                                                        .active_items()
-                                                        .map(|i| i.copied()),
+                                                    abilities: game.state.abilities.hand,
                                                     monsters: server.get_monsters().await?,
                                                     role: game.state.get_role(),
+                                                    health: game.state.health,
+                                                    piles: game.state.piles(),
                                                 },
                                             ),
                                         ),
@@ -631,13 +912,14 @@ pub async fn accept_connection(
                 _ => unreachable!("Reconnection in this context not allowed"),
             },
             DoneByConnectionType::New(NotifyServer(server, tx)) => {
+                let room = intro.room.expect("Entered a room");
                 let (home, NotifyServer(server, tx)) = done!(
                     run_peer!(
                         {
                             writer
                                 .send(encode_message(
                                     Msg::<server::SharedMsg, server::IntroMsg>::State(
-                                        server::IntroMsg::StartHome,
+                                        server::IntroMsg::StartHome(room),
                                     ),
                                 ))
                                 .await?;
@@ -678,18 +960,17 @@ pub async fn accept_connection(
                                     Msg::<server::SharedMsg, server::RolesMsg>::State(
                                         server::RolesMsg::StartGame(
                                             crate::protocol::client::StartGame {
-                                                abilities: game
-                                                    .state
-                                                    .abilities
-                                                    .active_items()
-                                                    .map(|i| i.copied()),
+                                                abilities: game.state.abilities.hand,
                                                 monsters: server.get_monsters().await?,
                                                 role: game.state.get_role(),
+                                                health: game.state.health,
+                                                piles: game.state.piles(),
                                             },
                                         ),
                                     ),
                                 ))
                                 .await?;
+                            server.sync_player(addr, game.state.clone()).await;
                         },
                         game,
                         server,
@@ -719,6 +1000,86 @@ where
     state.socket = None;
 }
 
+async fn login(
+    handle: &IntroHandle,
+    state: &mut Connection<states::IntroCmd>,
+    username: Username,
+    password: Password,
+    new_account: bool,
+    session: Option<SessionToken>,
+) -> anyhow::Result<()> {
+    use crate::protocol::server::LoginStatus;
+    // argon2 runs in this task, the Intro actor only looks up and stores hashes
+    let stored = state.server.get_password_hash(username.clone()).await?;
+    let account = match (new_account, stored) {
+        (true, Some(_)) => Err(AccountError::AlreadyExists),
+        (true, None) => accounts::hash_password(password).await.map(Some),
+        (false, Some(hash)) => accounts::verify_password(hash, password)
+            .await
+            .map(|()| None),
+        (false, None) => Err(AccountError::BadCredentials),
+    };
+    let logged = match account {
+        Ok(new_account) => state
+            .server
+            .login_player(state.addr, username, new_account, session, handle.clone())
+            .await
+            .context("Login failed")?,
+        Err(e) => {
+            info!(cause = %e, "Account rejected");
+            Err(match e {
+                AccountError::AlreadyExists => LoginStatus::AccountExists,
+                _ => LoginStatus::BadCredentials,
+            })
+        }
+    };
+    let status = match logged {
+        Ok(token) => {
+            state
+                .socket
+                .as_ref()
+                .unwrap()
+                .send(Msg::with(server::IntroMsg::Session(token)))
+                .await?;
+            LoginStatus::Logged
+        }
+        Err(status) => status,
+    };
+    info!(?status);
+    state
+        .socket
+        .as_ref()
+        .unwrap()
+        .send(Msg::with(server::IntroMsg::LoginStatus(status)))
+        .await?;
+    match status {
+        LoginStatus::Logged => (),
+        LoginStatus::Reconnected => {
+            state
+                .socket
+                .as_ref()
+                .expect("Must be open in this context")
+                .send(Msg::with(server::SharedMsg::ChatLog(
+                    state.server.get_chat_log(state.addr).await?.unwrap(),
+                )))
+                .await?;
+        }
+        // let the client try again a few times
+        LoginStatus::BadCredentials | LoginStatus::AccountExists => {
+            if handle.fail_login().await? >= MAX_LOGIN_ATTEMPTS {
+                warn!("Too many failed login attempts");
+                close_peer(state, handle).await;
+            }
+        }
+        _ => {
+            // connection fail
+            warn!(?status, "Login attempt rejected");
+            close_peer(state, handle).await;
+        }
+    }
+    Ok(())
+}
+
 #[async_trait::async_trait]
 impl<'a> AsyncMessageReceiver<client::IntroMsg, &'a mut Connection<states::IntroCmd>>
     for IntroHandle
@@ -730,39 +1091,12 @@ impl<'a> AsyncMessageReceiver<client::IntroMsg, &'a mut Connection<states::Intro
     ) -> anyhow::Result<()> {
         use client::IntroMsg;
 
-        use crate::protocol::server::LoginStatus;
         match msg {
-            IntroMsg::Login(username) => {
-                let status = state
-                    .server
-                    .login_player(state.addr, username, self.clone())
-                    .await
-                    .context("Login failed")?;
-                info!(?status);
-                state
-                    .socket
-                    .as_ref()
-                    .unwrap()
-                    .send(Msg::with(server::IntroMsg::LoginStatus(status)))
-                    .await?;
-                match status {
-                    LoginStatus::Logged => (),
-                    LoginStatus::Reconnected => {
-                        state
-                            .socket
-                            .as_ref()
-                            .expect("Must be open in this context")
-                            .send(Msg::with(server::SharedMsg::ChatLog(
-                                state.server.get_chat_log().await?.unwrap(),
-                            )))
-                            .await?;
-                    }
-                    _ => {
-                        // connection fail
-                        warn!(?status, "Login attempt rejected");
-                        close_peer(state, self).await;
-                    }
-                }
+            IntroMsg::Login(username, password, session) => {
+                login(self, state, username, password, false, session).await?;
+            }
+            IntroMsg::Register(username, password) => {
+                login(self, state, username, password, true, None).await?;
             }
             IntroMsg::GetChatLog => {
                 if state.server.is_peer_connected(state.addr).await? {
@@ -770,7 +1104,7 @@ impl<'a> AsyncMessageReceiver<client::IntroMsg, &'a mut Connection<states::Intro
                         // TODO get_chat_log if log only in other servers
                         state
                             .server
-                            .get_chat_log()
+                            .get_chat_log(state.addr)
                             .await
                             .map(|log| async {
                                 if let Some(log) = log {
@@ -786,8 +1120,30 @@ impl<'a> AsyncMessageReceiver<client::IntroMsg, &'a mut Connection<states::Intro
                     close_peer(state, self).await;
                 }
             }
-            IntroMsg::EnterGame => {
-                state.server.enter_game(state.addr).await;
+            IntroMsg::GetRooms => {
+                if state.server.is_peer_connected(state.addr).await? {
+                    if let Some(s) = state.socket.as_ref() {
+                        s.send(Msg::with(server::IntroMsg::Rooms(
+                            state.server.get_rooms().await?,
+                        )))
+                        .await?;
+                    }
+                } else {
+                    warn!("Client not logged but rooms were requested");
+                    close_peer(state, self).await;
+                }
+            }
+            IntroMsg::CreateRoom => {
+                state.server.enter_game(state.addr, None).await;
+            }
+            IntroMsg::JoinRoom(room) => {
+                state.server.enter_game(state.addr, Some(room)).await;
+            }
+            IntroMsg::WatchRoom(room) => {
+                state.server.watch_room(state.addr, room).await;
+            }
+            IntroMsg::StopWatching => {
+                state.server.stop_watching(state.addr).await;
             }
         }
 
@@ -819,6 +1175,21 @@ impl<'a>
         use client::HomeMsg;
         match msg {
             HomeMsg::Chat(msg) => broadcast_chat!(state.addr, self, state.server, msg),
+            HomeMsg::SetPlayerCount(count) => {
+                state.server.set_player_count(state.addr, count).await;
+            }
+            HomeMsg::AddBot => {
+                state.server.add_bot(state.addr, state.server.clone()).await;
+            }
+            HomeMsg::Ready(ready) => {
+                state.server.set_ready(state.addr, ready).await;
+            }
+            HomeMsg::Kick(whom) => {
+                state.server.kick(state.addr, whom).await;
+            }
+            HomeMsg::Lock(locked) => {
+                state.server.lock(state.addr, locked).await;
+            }
             HomeMsg::StartRoles => {
                 state.server.start_roles(state.addr).await;
             }
@@ -851,6 +1222,66 @@ impl<'a>
         Ok(())
     }
 }
+// the line may change under the hits, a monster that is gone
+// does not save the rest of the line
+pub(crate) async fn hit_whole_line<F, R>(
+    line: impl IntoIterator<Item = Card>,
+    mut hit: impl FnMut(Card) -> F,
+) -> Result<(), ActorError>
+where
+    F: std::future::Future<Output = Result<Result<(), R>, ActorError>>,
+{
+    for m in line {
+        if hit(m).await?.is_err() {
+            trace!("Monster {:?} is already gone", m);
+            continue;
+        }
+    }
+    Ok(())
+}
+// a move of the active player must fit the phase, the hand and the monster line,
+// the peer actor fails on invalid moves
+async fn validate_move(
+    peer: &GameHandle,
+    server: &states::GameHandle,
+    msg: &client::GameMsg,
+) -> anyhow::Result<Result<(), MoveRejection>> {
+    use client::GameMsg;
+    if server.is_game_over().await? {
+        return Ok(Err(MoveRejection::GameOver));
+    }
+    let expected = match msg {
+        GameMsg::Chat(_) => return Ok(Ok(())),
+        GameMsg::DropAbility(_) => GamePhaseKind::DropAbility,
+        GameMsg::SelectAbility(_) => GamePhaseKind::SelectAbility,
+        GameMsg::Attack(_) => GamePhaseKind::AttachMonster,
+        GameMsg::Continue => GamePhaseKind::Defend,
+    };
+    let phase = server.get_game_phase().await?;
+    if phase != expected {
+        return Ok(Err(MoveRejection::WrongPhase(phase)));
+    }
+    Ok(match msg {
+        GameMsg::DropAbility(rank) | GameMsg::SelectAbility(rank) => {
+            if peer.get_abilities().await?.contains(&Some(*rank)) {
+                Ok(())
+            } else {
+                Err(MoveRejection::NotInHand(*rank))
+            }
+        }
+        GameMsg::Attack(monster) => {
+            if peer.get_selected_ability().await?.is_none() {
+                Err(MoveRejection::NoSelectedAbility)
+            } else if !server.get_monsters().await?.contains(&Some(*monster)) {
+                Err(MoveRejection::NotOnMonsterLine(*monster))
+            } else {
+                Ok(())
+            }
+        }
+        GameMsg::Chat(_) | GameMsg::Continue => Ok(()),
+    })
+}
+
 #[async_trait::async_trait]
 impl<'a>
     AsyncMessageReceiver<
@@ -866,6 +1297,15 @@ impl<'a>
         use crate::protocol::server::TurnResult;
         macro_rules! turn {
             ($($msg:ident)::*($ok:expr)) => {
+                if state.server.get_active_player().await? == state.addr {
+                    if let Err(reason) = validate_move(self, &state.server, &msg).await? {
+                        info!(?reason, "Move rejected");
+                        state
+                            .send_tcp(Msg::with(server::GameMsg::MoveRejected(reason)))
+                            .await;
+                        return Ok(());
+                    }
+                }
                state.socket.as_ref()
                    .expect("Must be opened")
                    .send(Msg::with( $($msg)::*( {
@@ -878,6 +1318,9 @@ impl<'a>
                 }))).await?;
                 state.server.broadcast_game_state(state.addr).await;
                 self.sync_with_client().await;
+                if state.server.is_game_over().await? {
+                    state.server.log_out_players().await;
+                }
             }
         }
         use client::GameMsg;
@@ -897,8 +1340,7 @@ impl<'a>
             }
             GameMsg::Attack(card) => {
                 turn!(server::GameMsg::Attack({
-                    self.attack(card).await?;
-                    card
+                    self.attack(card).await?.map(|_| card)
                 }));
             }
             GameMsg::Continue => {
@@ -980,7 +1422,8 @@ impl<'a> AsyncMessageReceiver<IntroCmd, &'a mut ReduceState<Intro>> for Intro {
         state: &'a mut ReduceState<Intro>,
     ) -> anyhow::Result<()> {
         match msg {
-            IntroCmd::EnterGame(server, tx) => {
+            IntroCmd::EnterGame(server, room, tx) => {
+                self.room = Some(room);
                 state
                     .done
                     .take()
@@ -1006,12 +1449,19 @@ impl<'a> AsyncMessageReceiver<IntroCmd, &'a mut ReduceState<Intro>> for Intro {
             }
             IntroCmd::SendTcp(msg) => {
                 if let Some(s) = state.connection.socket.as_ref() {
-                    s.send(msg).await?;
+                    // a spectator can get game messages after disconnection
+                    if s.send(msg).await.is_err() {
+                        state.connection.close_socket();
+                    }
                 }
             }
             IntroCmd::SetUsername(name) => {
                 self.username = Some(name);
             }
+            IntroCmd::FailLogin(tx) => {
+                self.failed_logins += 1;
+                let _ = tx.send(self.failed_logins);
+            }
         }
         Ok(())
     }
@@ -1090,6 +1540,63 @@ impl<'a> AsyncMessageReceiver<RolesCmd, &'a mut ReduceState<Roles>> for Peer<Rol
     }
 }
 
+impl Peer<Game> {
+    // refill the hand, the exhaustion damage of a reshuffle can defeat the player
+    async fn draw_abilities(&mut self, state: &ReduceState<Game>) -> anyhow::Result<()> {
+        let penalty = self.state.abilities.draw();
+        if penalty == 0 {
+            return Ok(());
+        }
+        self.state.health = self.state.health.saturating_sub(penalty);
+        let event = server::ChatLine::GameEvent(format!(
+            "{} is exhausted and gets {} damage",
+            self.username, penalty
+        ));
+        state.connection.server.append_chat(event.clone()).await;
+        state
+            .connection
+            .server
+            .broadcast_to_all(Msg::with(server::SharedMsg::Chat(event)))
+            .await?;
+        if self.state.health == 0 {
+            state
+                .connection
+                .server
+                .player_defeated(state.connection.addr)
+                .await;
+        }
+        Ok(())
+    }
+    // the server saves the game at the end of a turn with the last state of the player
+    async fn end_turn(&self, state: &mut ReduceState<Game>) -> anyhow::Result<()> {
+        state
+            .connection
+            .server
+            .sync_player(state.connection.addr, self.state.clone())
+            .await;
+        state.connection.server.switch_to_next_player().await?;
+        Ok(())
+    }
+    async fn sync_with_client(&self, state: &mut ReduceState<Game>) -> anyhow::Result<()> {
+        state
+            .connection
+            .server
+            .sync_player(state.connection.addr, self.state.clone())
+            .await;
+        let data = server::GameData {
+            monsters: state.connection.server.get_monsters().await?,
+            abilities: self.state.abilities.hand,
+            health: self.state.health,
+            piles: self.state.piles(),
+        };
+        state
+            .connection
+            .send_tcp(Msg::State(server::GameMsg::UpdateGameData(data)))
+            .await;
+        Ok(())
+    }
+}
+
 #[async_trait::async_trait]
 impl<'a> AsyncMessageReceiver<GameCmd, &'a mut ReduceState<Game>> for Peer<Game> {
     async fn reduce(
@@ -1105,94 +1612,191 @@ impl<'a> AsyncMessageReceiver<GameCmd, &'a mut ReduceState<Game>> for Peer<Game>
                         username: Username::default(),
                         state: Game {
                             selected_ability: None,
-                            abilities: Stateble::with_items(AbilityDeck::new(Suit::Clubs)),
+                            abilities: AbilityDeck::new(Suit::Clubs),
                             health: 0,
+                            skip_monster_attack: false,
                         },
                     },
                 ));
             }
             GameCmd::SendTcp(msg) => {
-                state
-                    .connection
-                    .socket
-                    .as_ref()
-                    .expect("Open here")
-                    .send(msg)
-                    .await?;
+                state.connection.send_tcp(msg).await;
             }
             GameCmd::GetAbilities(tx) => {
-                let _ = tx.send(self.state.abilities.active_items().map(|i| i.copied()));
+                let _ = tx.send(self.state.abilities.hand);
+            }
+            GameCmd::GetSelectedAbility(tx) => {
+                let _ = tx.send(self.state.selected_ability);
             }
             GameCmd::DropAbility(ability, tx) => {
-                let i = self
-                    .state
-                    .abilities
-                    .items
-                    .ranks
-                    .iter()
-                    .position(|i| *i == ability)
-                    .ok_or_else(|| anyhow::anyhow!("Bad ability to drop {:?}", ability))?;
-                self.state.abilities.deactivate_item_by_index(i)?;
-
-                state.connection.server.switch_to_next_player().await?;
+                self.state.abilities.discard(ability)?;
+                if Role::from(self.state.get_role()).shields_on_drop() {
+                    state
+                        .connection
+                        .server
+                        .raise_shield(state.connection.addr, ability.value())
+                        .await;
+                }
+
+                self.end_turn(state).await?;
                 let _ = tx.send(());
             }
             GameCmd::SelectAbility(ability, tx) => {
-                self.state.selected_ability = Some(
-                    self.state
-                        .abilities
-                        .items
-                        .ranks
-                        .iter()
-                        .position(|i| *i == ability)
-                        .ok_or_else(|| anyhow::anyhow!("This ability not exists {:?}", ability))?,
-                );
-                state.connection.server.switch_to_next_player().await?;
+                if !self.state.abilities.in_hand(ability) {
+                    anyhow::bail!("This ability not exists {:?}", ability);
+                }
+                self.state.selected_ability = Some(ability);
+                for effect in effects::of(ability, self.state.get_role()) {
+                    match *effect {
+                        Effect::Heal(health) => {
+                            self.state.health = (self.state.health + health).min(MAX_HEALTH)
+                        }
+                        Effect::Draw => self.draw_abilities(state).await?,
+                        Effect::SkipMonsterAttack => self.state.skip_monster_attack = true,
+                        // applied on attack
+                        Effect::Damage(_) | Effect::SwapMonster => (),
+                    }
+                }
+                self.end_turn(state).await?;
                 let _ = tx.send(());
             }
 
             GameCmd::Attack(monster, tx) => {
-                #[allow(unused)]
+                let role = Role::from(self.state.get_role());
                 let ability = self
                     .state
                     .selected_ability
                     .ok_or_else(|| anyhow::anyhow!("Ability is not selected"))?;
-                // TODO responce send to client
-                // if monster.rank as u16 > self.abilities.items.ranks[ability] as u16 {
-                //     return Err(anyhow!(
-                //         "Wrong monster to attack = {:?}, ability = {:?}",
-                //         monster.rank,
-                //        self.abilities.items.ranks[ability]
-                //     ));
-                //} else {
-                state.connection.server.drop_monster(monster).await??;
-                trace!(
-                    "Drop monster {:?}, all now {:?}",
-                    monster,
-                    state.connection.server.get_monsters().await
-                );
-                //}
-                state.connection.server.switch_to_next_player().await?;
-                let _ = tx.send(());
+                let effects = effects::of(ability, self.state.get_role());
+                if role.can_defeat(ability, &monster) {
+                    state.connection.server.hit_monster(monster).await??;
+                    let extra_hits: u8 = effects
+                        .iter()
+                        .map(|e| match e {
+                            Effect::Damage(hits) => *hits,
+                            _ => 0,
+                        })
+                        .sum();
+                    for _ in 0..extra_hits {
+                        // the monster may be already defeated
+                        if state.connection.server.hit_monster(monster).await?.is_err() {
+                            break;
+                        }
+                    }
+                    if role.hits_whole_line(ability) {
+                        let server = &state.connection.server;
+                        let line = server.get_monsters().await?;
+                        hit_whole_line(line.into_iter().flatten().filter(|m| *m != monster), |m| {
+                            server.hit_monster(m)
+                        })
+                        .await?;
+                    }
+                    trace!(
+                        "Hit monster {:?}, all now {:?}",
+                        monster,
+                        state.connection.server.get_monsters().await
+                    );
+                    self.state.abilities.discard(ability)?;
+                    self.state.selected_ability = None;
+                    self.end_turn(state).await?;
+                    let _ = tx.send(Ok(()));
+                } else {
+                    let swapped = if effects.contains(&Effect::SwapMonster) {
+                        Some(state.connection.server.swap_monster(monster).await?)
+                    } else {
+                        None
+                    };
+                    if let Some(Ok(())) = swapped {
+                        self.state.abilities.discard(ability)?;
+                        self.state.selected_ability = None;
+                        self.end_turn(state).await?;
+                        let _ = tx.send(Ok(()));
+                    } else if role
+                        .has_target(ability, &state.connection.server.get_monsters().await?)
+                    {
+                        // keep the turn while there is a monster to defeat by this ability
+                        let _ = tx.send(Err(match swapped {
+                            Some(Err(e)) => {
+                                info!(cause = %e, ?monster, "Monster is not swapped");
+                                AttackError::NothingToSwap { monster }
+                            }
+                            _ => AttackError::TooWeak { ability, monster },
+                        }));
+                    } else {
+                        // the effects of a useless ability are not for free
+                        self.state.abilities.discard(ability)?;
+                        self.state.selected_ability = None;
+                        self.end_turn(state).await?;
+                        let _ = tx.send(Err(AttackError::NoTarget { ability }));
+                    }
+                }
             }
             GameCmd::ContinueGame(tx) => {
-                // TODO end of deck, handle game finish
-                let _ = self.state.abilities.next_actives();
+                self.draw_abilities(state).await?;
                 state.connection.server.continue_game_cycle().await?;
-                state.connection.server.switch_to_next_player().await?;
+                self.end_turn(state).await?;
                 let _ = tx.send(());
             }
             GameCmd::SyncWithClient() => {
+                self.sync_with_client(state).await?;
+            }
+            GameCmd::LeaveGame() => {
+                self.sync_with_client(state).await?;
                 state
                     .connection
-                    .socket
-                    .as_ref()
-                    .unwrap()
-                    .send(Msg::State(server::GameMsg::UpdateGameData((
-                        state.connection.server.get_monsters().await?,
-                        self.state.abilities.active_items().map(|i| i.copied()),
-                    ))))
-                    .await?;
+                    .send_tcp(Msg::Shared(server::SharedMsg::Logout))
+                    .await;
+            }
+            GameCmd::Defend(mut monster, shield) => {
+                if self.state.skip_monster_attack {
+                    if let Some(m) = monster.take() {
+                        self.state.skip_monster_attack = false;
+                        let event = server::ChatLine::GameEvent(format!(
+                            "{} avoids the attack of {:?}",
+                            self.username, m
+                        ));
+                        state.connection.server.append_chat(event.clone()).await;
+                        state
+                            .connection
+                            .server
+                            .broadcast_to_all(Msg::with(server::SharedMsg::Chat(event)))
+                            .await?;
+                    }
+                }
+                if let Some(monster) = monster {
+                    let damage = Role::from(self.state.get_role())
+                        .take_damage(monster.damage())
+                        .saturating_sub(shield);
+                    self.state.health = self.state.health.saturating_sub(damage);
+                    let event = server::ChatLine::GameEvent(format!(
+                        "{} gets {} damage from {:?}",
+                        self.username, damage, monster
+                    ));
+                    state.connection.server.append_chat(event.clone()).await;
+                    state
+                        .connection
+                        .server
+                        .broadcast_to_all(Msg::with(server::SharedMsg::Chat(event)))
+                        .await?;
+                    if self.state.health == 0 {
+                        state
+                            .connection
+                            .server
+                            .player_defeated(state.connection.addr)
+                            .await;
+                    }
+                }
+                // the monster may be swapped in by the last attack of the round,
+                // the client must see the new line before the attack
+                self.sync_with_client(state).await?;
+                state
+                    .connection
+                    .send_tcp(Msg::with(server::GameMsg::Defend(monster)))
+                    .await;
+                // the last defender may end the game
+                if state.connection.server.is_game_over().await? {
+                    state.connection.server.log_out_players().await;
+                }
             }
         }
         Ok(())
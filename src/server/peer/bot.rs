@@ -0,0 +1,350 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use arraystring::ArrayString;
+use tokio::sync::{mpsc::channel, oneshot};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use super::{
+    Connection, Game, GameCmd, GameHandle, Home, HomeCmd, HomeHandle, NotifyServer, Peer,
+    ReduceState, Roles, RolesHandle, SharedCmd,
+};
+use crate::{
+    game::{Card, Rank, Role},
+    protocol::{
+        client, server,
+        server::{AttackError, SelectRoleError},
+        AsyncMessageReceiver, GamePhaseKind, Msg, RoleStatus, TurnStatus, Username,
+    },
+    server::{states, Handle, Rx, MPSC_CHANNEL_CAPACITY},
+};
+
+// bots have no socket, real clients never come from this address
+const BOT_IP: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 1);
+// a pause before every decision, so players can follow the moves of bots
+const THINK_TIME: Duration = Duration::from_millis(300);
+
+#[inline]
+pub fn is_bot(addr: &SocketAddr) -> bool {
+    addr.ip() == BOT_IP
+}
+
+#[inline]
+pub fn addr(id: u16) -> SocketAddr {
+    SocketAddr::from((BOT_IP, id))
+}
+
+// a name is unique while the server runs
+pub fn new_bot() -> (SocketAddr, Username) {
+    static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let name = ArrayString::try_from_str(format!("Bot{}", id))
+        .map(Username::new)
+        .expect("Fits a username")
+        .expect("Longer than one char");
+    (addr(id), name)
+}
+
+// decisions of a bot, the server validates them as moves of a player
+pub trait Strategy: Send + Sync {
+    fn select_role(&self, roles: &[RoleStatus]) -> Option<Role>;
+    fn drop_ability(&self, role: Role, hand: &[Rank]) -> Option<Rank>;
+    fn select_ability(&self, role: Role, hand: &[Rank], monsters: &[Card]) -> Option<Rank>;
+    fn attack(&self, role: Role, ability: Rank, monsters: &[Card]) -> Option<Card>;
+}
+
+// keeps strong abilities and beats the strongest monster it can
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Greedy;
+
+impl Strategy for Greedy {
+    fn select_role(&self, roles: &[RoleStatus]) -> Option<Role> {
+        roles.iter().find_map(|r| match r {
+            RoleStatus::Available(role) => Some(*role),
+            RoleStatus::NotAvailable(_) => None,
+        })
+    }
+    fn drop_ability(&self, _: Role, hand: &[Rank]) -> Option<Rank> {
+        hand.iter().copied().min_by_key(|a| a.value())
+    }
+    fn select_ability(&self, role: Role, hand: &[Rank], monsters: &[Card]) -> Option<Rank> {
+        hand.iter()
+            .copied()
+            .filter(|a| monsters.iter().any(|m| role.can_defeat(*a, m)))
+            .min_by_key(|a| a.value())
+            .or_else(|| hand.iter().copied().max_by_key(|a| a.value()))
+    }
+    fn attack(&self, role: Role, ability: Rank, monsters: &[Card]) -> Option<Card> {
+        monsters
+            .iter()
+            .copied()
+            .filter(|m| role.can_defeat(ability, m))
+            .max_by_key(|m| m.rank as u16)
+            .or_else(|| monsters.iter().copied().min_by_key(|m| m.rank as u16))
+    }
+}
+
+// runs the peer actor of a state and answers its socket messages by the bot.
+// The next state of the actor cancels an unfinished decision, a server
+// in transition waits for the new handle of the peer
+macro_rules! run_bot {
+    ($visitor:expr, $server:expr, $peer_rx:expr, $addr:expr,
+     |$connection:ident| $start_block:block, |$msg:ident| $on_msg:block) => {
+        async {
+            let (to_socket, mut socket_rx) = channel(MPSC_CHANNEL_CAPACITY);
+            #[allow(unused_mut)]
+            let mut $connection = Connection::new($addr, $server, to_socket);
+            let peer = tokio::spawn({
+                let connection = $connection.clone();
+                let mut visitor = $visitor;
+                let mut peer_rx = $peer_rx;
+                async move { run_state!(visitor, connection, peer_rx, $addr).await }
+            });
+            let bot = async {
+                $start_block;
+                while let Some($msg) = socket_rx.recv().await $on_msg
+                Ok::<(), anyhow::Error>(())
+            };
+            let result = tokio::select! {
+                new_state = peer => return new_state.context("tokio::join Error")?,
+                result = bot => result,
+            };
+            $connection.server.drop_peer($addr).await;
+            result.map(|_| None)
+        }
+    };
+}
+
+fn new_handle<T>() -> (Handle<T>, Rx<T>) {
+    let (tx, rx) = channel(MPSC_CHANNEL_CAPACITY);
+    (Handle::for_tx(tx), rx)
+}
+
+// a bot takes a seat in the lobby and goes through Roles and Game
+// like a connected player, but without a socket
+pub fn join_home(
+    addr: SocketAddr,
+    username: Username,
+    server: states::HomeHandle,
+    strategy: Arc<dyn Strategy>,
+) -> HomeHandle {
+    let (handle, peer_rx) = new_handle();
+    tokio::spawn(
+        async move {
+            if let Err(e) = play(addr, username, server, peer_rx, strategy).await {
+                error!(cause = %e, "Bot error");
+            }
+        }
+        .instrument(info_span!("Bot", %addr)),
+    );
+    handle
+}
+
+// a bot of a game restored from a snapshot plays on at once
+pub fn restore_game(
+    addr: SocketAddr,
+    game: Peer<Game>,
+    server: states::GameHandle,
+    strategy: Arc<dyn Strategy>,
+) -> GameHandle {
+    let (handle, peer_rx) = new_handle();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            if let Err(e) = play_game(addr, game, server, handle, peer_rx, strategy, true).await {
+                error!(cause = %e, "Bot error");
+            }
+        }
+        .instrument(info_span!("Bot", %addr))
+    });
+    handle
+}
+
+async fn play(
+    addr: SocketAddr,
+    username: Username,
+    server: states::HomeHandle,
+    peer_rx: Rx<Msg<SharedCmd, HomeCmd>>,
+    strategy: Arc<dyn Strategy>,
+) -> anyhow::Result<()> {
+    let (home, NotifyServer(server, tx)) = done!(
+        run_bot!(
+            Peer::<Home> {
+                username,
+                state: Home
+            },
+            server,
+            peer_rx,
+            addr,
+            |connection| {},
+            |_msg| {}
+        )
+        .instrument(info_span!("Home"))
+        .await?
+    );
+
+    let (mut handle, peer_rx): (RolesHandle, _) = new_handle();
+    let _ = tx.send(handle.clone());
+    let (roles, NotifyServer(server, tx)) = done!(
+        run_bot!(
+            Peer::<Roles>::from(home),
+            server,
+            peer_rx,
+            addr,
+            |connection| {
+                select_role(&mut handle, &mut connection, &*strategy).await?;
+            },
+            |msg| {
+                match msg {
+                    // a role of the bot has been taken first
+                    Msg::State(server::RolesMsg::SelectedStatus(Err(SelectRoleError::Busy))) => {
+                        select_role(&mut handle, &mut connection, &*strategy).await?
+                    }
+                    Msg::State(server::RolesMsg::AvailableRoles(_))
+                        if handle.get_role().await?.is_none() =>
+                    {
+                        select_role(&mut handle, &mut connection, &*strategy).await?
+                    }
+                    _ => (),
+                }
+            }
+        )
+        .instrument(info_span!("Roles"))
+        .await?
+    );
+
+    let game = Peer::<Game>::from(roles);
+    let (handle, peer_rx) = new_handle();
+    let _ = tx.send(handle.clone());
+    server.sync_player(addr, game.state.clone()).await;
+    play_game(addr, game, server, handle, peer_rx, strategy, false)
+        .instrument(info_span!("Game"))
+        .await
+}
+
+async fn select_role(
+    handle: &mut RolesHandle,
+    connection: &mut Connection<Msg<states::SharedCmd, states::RolesCmd>>,
+    strategy: &dyn Strategy,
+) -> anyhow::Result<()> {
+    // players choose first
+    tokio::time::sleep(THINK_TIME).await;
+    let roles = connection.server.get_available_roles().await?;
+    if let Some(role) = strategy.select_role(&roles) {
+        handle
+            .reduce(client::RolesMsg::Select(role), connection)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn play_game(
+    addr: SocketAddr,
+    game: Peer<Game>,
+    server: states::GameHandle,
+    mut handle: GameHandle,
+    peer_rx: Rx<Msg<SharedCmd, GameCmd>>,
+    strategy: Arc<dyn Strategy>,
+    resume: bool,
+) -> anyhow::Result<()> {
+    let role = Role::from(game.state.get_role());
+    done!(
+        run_bot!(
+            game,
+            server,
+            peer_rx,
+            addr,
+            |connection| {
+                // nobody sends the turn again to a restored game
+                if resume && connection.server.get_active_player().await? == addr {
+                    let phase = connection.server.get_game_phase().await?;
+                    play_turn(&mut handle, &mut connection, &*strategy, role, phase).await?;
+                }
+            },
+            |msg| {
+                match msg {
+                    Msg::State(server::GameMsg::Turn(TurnStatus::Ready(phase))) => {
+                        play_turn(&mut handle, &mut connection, &*strategy, role, phase).await?
+                    }
+                    // the turn stays while some monster can be defeated
+                    Msg::State(server::GameMsg::Attack(Ok(Err(
+                        AttackError::TooWeak { .. } | AttackError::NothingToSwap { .. },
+                    )))) => {
+                        play_turn(
+                            &mut handle,
+                            &mut connection,
+                            &*strategy,
+                            role,
+                            GamePhaseKind::AttachMonster,
+                        )
+                        .await?
+                    }
+                    Msg::State(server::GameMsg::Attack(Ok(Err(AttackError::NoTarget {
+                        ability,
+                    })))) => {
+                        info!(?ability, "Bot has lost the turn");
+                    }
+                    Msg::State(server::GameMsg::MoveRejected(reason)) => {
+                        warn!(?reason, "Bot move rejected");
+                    }
+                    _ => (),
+                }
+            }
+        )
+        .await?
+    );
+    Ok(())
+}
+
+async fn play_turn(
+    handle: &mut GameHandle,
+    connection: &mut Connection<Msg<states::SharedCmd, states::GameCmd>>,
+    strategy: &dyn Strategy,
+    role: Role,
+    phase: GamePhaseKind,
+) -> anyhow::Result<()> {
+    tokio::time::sleep(THINK_TIME).await;
+    let hand: Vec<Rank> = handle
+        .get_abilities()
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    let monsters: Vec<Card> = connection
+        .server
+        .get_monsters()
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    let msg = match phase {
+        GamePhaseKind::DropAbility => strategy
+            .drop_ability(role, &hand)
+            .map(client::GameMsg::DropAbility),
+        GamePhaseKind::SelectAbility => strategy
+            .select_ability(role, &hand, &monsters)
+            .map(client::GameMsg::SelectAbility),
+        GamePhaseKind::AttachMonster => match handle.get_selected_ability().await? {
+            Some(ability) => strategy
+                .attack(role, ability, &monsters)
+                .map(client::GameMsg::Attack),
+            None => None,
+        },
+        GamePhaseKind::Defend => Some(client::GameMsg::Continue),
+    };
+    match msg {
+        Some(msg) => {
+            debug!(?msg, "Bot move");
+            handle.reduce(msg, connection).await?;
+        }
+        None => info!(?phase, "Bot has no move"),
+    }
+    Ok(())
+}
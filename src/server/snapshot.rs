@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context as _;
+use arrayvec::ArrayVec;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn};
+
+use super::{details::ActiveState, peer};
+use crate::{
+    game::{Card, Deck},
+    protocol::{
+        server::{ChatLine, GameStats, PlayerView, RoomCode, MAX_PLAYER_COUNT},
+        GamePhaseKind, SessionToken,
+    },
+};
+
+const GAME_PREFIX: &str = "game-";
+const SESSIONS_FILE: &str = "sessions.json";
+
+// a running game in a form to continue it after a server restart.
+// Players are referred by their index because addresses change on reconnection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub code: RoomCode,
+    pub chat: Vec<ChatLine>,
+    pub monsters: Deck,
+    pub monster_line: ArrayVec<ActiveState, MAX_PLAYER_COUNT>,
+    pub active_player: ArrayVec<ActiveState, 1>,
+    pub phase: GamePhaseKind,
+    pub wounds: ArrayVec<(Card, u8), MAX_PLAYER_COUNT>,
+    pub defeated: ArrayVec<usize, MAX_PLAYER_COUNT>,
+    pub shield: Option<(usize, u16)>,
+    pub players: ArrayVec<(PlayerView, peer::Game), MAX_PLAYER_COUNT>,
+    pub stats: GameStats,
+    // seats of bots, they play on after a restart
+    #[serde(default)]
+    pub bots: Vec<usize>,
+}
+
+// changes of the snapshot directory in the order of requests
+#[derive(Debug)]
+enum Change {
+    SaveGame(Box<GameSnapshot>),
+    RemoveGame(RoomCode),
+    SaveSessions(HashMap<String, SessionToken>),
+    Flush(oneshot::Sender<()>),
+}
+
+// a directory with a json file per running game and the last sessions of players.
+// Files are written by a writer task, so the actors of rooms don't wait for the disk
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    dir: Arc<PathBuf>,
+    writer: mpsc::UnboundedSender<Change>,
+}
+
+impl Snapshots {
+    pub fn new(dir: PathBuf) -> Self {
+        let dir = Arc::new(dir);
+        let (writer, changes) = mpsc::unbounded_channel();
+        tokio::spawn(run_writer(dir.clone(), changes));
+        Snapshots { dir, writer }
+    }
+
+    pub fn save(&self, game: GameSnapshot) {
+        self.change(Change::SaveGame(Box::new(game)));
+    }
+
+    pub fn remove(&self, code: RoomCode) {
+        self.change(Change::RemoveGame(code));
+    }
+
+    pub fn save_sessions(&self, sessions: HashMap<String, SessionToken>) {
+        self.change(Change::SaveSessions(sessions));
+    }
+
+    // waits until all previous changes are on the disk
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.change(Change::Flush(tx));
+        let _ = rx.await;
+    }
+
+    fn change(&self, change: Change) {
+        if self.writer.send(change).is_err() {
+            error!(dir = %self.dir.display(), "The snapshot writer has stopped");
+        }
+    }
+
+    pub fn load_game(&self, code: RoomCode) -> Option<GameSnapshot> {
+        let file = game_file(&self.dir, code);
+        read(&file)
+            .inspect_err(|e| warn!(file = %file.display(), cause = %e, "Skip a game snapshot"))
+            .ok()
+    }
+
+    // broken files are skipped, the server starts anyway
+    pub fn load_games(&self) -> Vec<GameSnapshot> {
+        let entries = match std::fs::read_dir(&*self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!(dir = %self.dir.display(), cause = %e, "Failed to read snapshots");
+                }
+                return Vec::new();
+            }
+        };
+        entries
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(GAME_PREFIX) && n.ends_with(".json"))
+            })
+            .filter_map(|p| {
+                read(&p)
+                    .inspect_err(|e| warn!(file = %p.display(), cause = %e, "Skip a game snapshot"))
+                    .ok()
+            })
+            .collect()
+    }
+
+    pub fn load_sessions(&self) -> HashMap<String, SessionToken> {
+        let file = self.dir.join(SESSIONS_FILE);
+        if !file.exists() {
+            return HashMap::default();
+        }
+        read(&file)
+            .inspect_err(|e| warn!(cause = %e, "Skip sessions"))
+            .unwrap_or_default()
+    }
+}
+
+async fn run_writer(dir: Arc<PathBuf>, mut changes: mpsc::UnboundedReceiver<Change>) {
+    while let Some(change) = changes.recv().await {
+        let dir = dir.clone();
+        // serialization and the disk are blocking
+        if let Err(e) = tokio::task::spawn_blocking(move || apply(&dir, change)).await {
+            error!(cause = %e, "Snapshot writer task failed");
+        }
+    }
+}
+
+fn apply(dir: &Path, change: Change) {
+    match change {
+        Change::SaveGame(game) => {
+            if let Err(e) = write(dir, &game_file(dir, game.code), &game) {
+                error!(room = game.code, cause = %e, "Failed to save a game snapshot");
+            }
+        }
+        Change::RemoveGame(code) => {
+            let file = game_file(dir, code);
+            if let Err(e) = std::fs::remove_file(&file) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!(file = %file.display(), cause = %e, "Failed to remove a game snapshot");
+                }
+            }
+        }
+        Change::SaveSessions(sessions) => {
+            if let Err(e) = write(dir, &dir.join(SESSIONS_FILE), &sessions) {
+                error!(cause = %e, "Failed to save sessions");
+            }
+        }
+        Change::Flush(done) => {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn game_file(dir: &Path, code: RoomCode) -> PathBuf {
+    dir.join(format!("{}{}.json", GAME_PREFIX, code))
+}
+
+fn write(dir: &Path, file: &Path, value: &impl Serialize) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    // a crash in the middle of writing must not break the previous snapshot
+    let tmp = file.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_string(value)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, file).with_context(|| format!("Failed to write {}", file.display()))
+}
+
+fn read<T: serde::de::DeserializeOwned>(file: &Path) -> anyhow::Result<T> {
+    let data = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    serde_json::from_str(&data).with_context(|| format!("Invalid {}", file.display()))
+}
@@ -1,23 +1,33 @@
-use std::net::SocketAddr;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+};
 
 use arrayvec::ArrayVec;
 use futures::stream::StreamExt;
+use rand::Rng;
 use tokio::sync::{mpsc, mpsc::channel, oneshot};
-use tracing::{debug, error, info, info_span, trace, Instrument};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
 use super::{
-    details::{Stateble, StatebleItem},
+    accounts::Accounts,
+    details::{ActiveState, DeactivateItemError, Stateble, StatebleItem},
     peer,
-    peer::PeerHandle,
+    peer::{bot, PeerHandle},
+    snapshot::{GameSnapshot, Snapshots},
     Answer, Handle, Rx, Tx, MPSC_CHANNEL_CAPACITY,
 };
 use crate::{
     game::{Card, Deck, Role},
     protocol::{
         server,
-        server::{ChatLine, LoginStatus, PlayerId, SharedMsg, MAX_PLAYER_COUNT},
+        server::{
+            ChatLine, EnterRoomError, LoginStatus, PlayerId, RoomCode, SharedMsg,
+            DEFAULT_PLAYER_COUNT, MAX_PLAYER_COUNT, MAX_ROOM_COUNT,
+        },
         AsyncMessageReceiver, GameContext, GameContextKind, GamePhaseKind, Msg, SendSocketMessage,
-        Username,
+        SessionToken, Username,
     },
 };
 
@@ -43,12 +53,6 @@ macro_rules! done {
     };
 }
 
-macro_rules! recv {
-    ($result:expr) => {
-        $result.expect("Peer actor is connected")
-    };
-}
-
 #[tracing::instrument(skip_all, name = "Intro")]
 pub async fn run_intro_server(
     StartServer {
@@ -56,16 +60,25 @@ pub async fn run_intro_server(
         server_rx: intro_rx,
     }: &mut StartServer<IntroServer, Rx<IntroCmd>>,
 ) -> anyhow::Result<()> {
-    let (notify_new_server, mut rx) = channel::<ServerHandleByContext>(1);
+    let (notify_new_server, mut rx) = channel::<(RoomCode, RoomEvent)>(1);
+    intro.restore_games(&notify_new_server);
     loop {
         let (cancel, mut cancel_rx) = oneshot::channel();
         let mut state = ServerState::new(cancel);
         tokio::select! {
-            new_server = rx.recv() => {
-                debug!(server = ?new_server.as_ref().map(|s| GameContextKind::from(&s.0))
-                       , "Set new state");
-                intro.game_server = new_server;
-            }
+            event = rx.recv() => match event {
+                Some((code, RoomEvent::NewServer(server))) => {
+                    debug!(room = code, server = ?GameContextKind::from(&server.0), "Set new state");
+                    // a room may be already closed
+                    if let Some(room) = intro.rooms.iter_mut().find(|r| r.code == code) {
+                        room.server = server;
+                    }
+                }
+                Some((code, RoomEvent::Stopped)) => {
+                    intro.recover_room(code, &notify_new_server);
+                }
+                None => (),
+            },
             // run intro server
             next = async {
                 loop{
@@ -89,94 +102,103 @@ pub async fn run_intro_server(
             } => {
 
                 let sender = done!(next?);
-                // start a new state machine
-                if intro.game_server.is_none() {
-                    trace!(?sender, "Start new server = Home");
-                    let home = StartServer::async_from((sender, &mut *intro)).await;
-                    tokio::spawn({
-                        let notify_new_server = notify_new_server.clone();
-                        async move {
-                            if let Err(e) = run_server(home, notify_new_server).await {
-                                error!("State server error = {:#}", e)
-                            }
-                        }
-                    });
-                // connect to existing state machine
-                } else {
-                    let server = intro.game_server.as_ref().unwrap();
-                    trace!(?sender, server = ?GameContextKind::from(&server.0), "Connect to new server state");
-                    let peer_slot = intro
-                        .peers
-                        .0
-                        .iter_mut()
-                        .find(|p| p.addr == sender)
-                        .ok_or(PeerNotFound(sender))?;
-
-                    // uses by Roles and Game
-                    macro_rules! reconnection {
-                        ($server:expr, $peer_slot:expr, $reconnect_function:ident) => {{
-                            let name = recv!($peer_slot.peer.as_ref().unwrap().get_username().await);
-                            let PeerSlot {
-                                addr,
-                                peer: old_handle,
-                            } = recv!($server.get_peer_handle_by_username(name.clone()).await).expect(&format!(
-                                "Peer with name = {} must exists until reconnection",
-                                name
-                            ));
-                            let new_peer_handle = recv!(
-                                $peer_slot
-                                    .peer
-                                    .as_ref()
-                                    .unwrap()
-                                    .$reconnect_function($server.clone(), old_handle.clone())
-                                    .await
-                            );
-                            drop(old_handle);
-                            recv!(
-                                $server
-                                    .reconnect_peer(addr, ($peer_slot.addr, new_peer_handle))
-                                    .await
-                            );
-                        }};
-                    }
-                    match &server.0 {
-                        GameContext::Home(h) => {
-                            let handle =
-                                recv!(peer_slot.peer.as_ref().unwrap().enter_game(h.clone()).await);
-                            recv!(h.add_peer(peer_slot.addr, handle)
-                                .await)
-                                .expect("Peer slot must be empty");
+                // a peer may leave at any moment, it only fails to enter
+                if let Err(e) = async {
+                    match intro.rooms.iter().position(|r| r.players.contains(&sender)) {
+                        // start a new state machine
+                        None => {
+                            let code = intro.new_room_code();
+                            trace!(?sender, room = code, "Start new server = Home");
+                            let home = StartServer::async_try_from((sender, code, &mut *intro)).await?;
+                            let notifier = RoomNotifier {
+                                code,
+                                tx: notify_new_server.clone(),
+                                snapshots: intro.snapshots.clone(),
+                            };
+                            supervise(code, notify_new_server.clone(), run_server(home, notifier));
                         }
-                        // Reconnection here
-                        GameContext::Roles(r) => {
-                            reconnection!(r, peer_slot, reconnect_roles);
-                        }
-                        GameContext::Game(g) => {
-                            reconnection!(g, peer_slot, reconnect_game);
+                        // connect to existing state machine
+                        Some(i) => {
+                            let room = &intro.rooms[i];
+                            let server = &room.server;
+                            trace!(?sender, room = room.code, server = ?GameContextKind::from(&server.0), "Connect to new server state");
+                            let peer_slot = intro
+                                .peers
+                                .0
+                                .iter_mut()
+                                .find(|p| p.addr == sender)
+                                .ok_or(PeerNotFound(sender))?;
+
+                            // uses by Roles and Game
+                            macro_rules! reconnection {
+                                ($server:expr, $peer_slot:expr, $reconnect_function:ident) => {{
+                                    let name = $peer_slot.name.clone();
+                                    let PeerSlot {
+                                        addr,
+                                        peer: old_handle,
+                                        ..
+                                    } = $server.get_peer_handle_by_username(name.clone()).await?.ok_or_else(|| anyhow::anyhow!(
+                                        "Peer with name = {} must exists until reconnection",
+                                        name
+                                    ))?;
+                                    let new_peer_handle = $peer_slot
+                                        .peer
+                                        .as_ref()
+                                        .unwrap()
+                                        .$reconnect_function($server.clone(), old_handle.clone())
+                                        .await?;
+                                    drop(old_handle);
+                                    $server
+                                        .reconnect_peer(addr, ($peer_slot.addr, new_peer_handle))
+                                        .await??;
+                                }};
+                            }
+                            match &server.0 {
+                                GameContext::Home(h) => {
+                                    let handle = peer_slot
+                                        .peer
+                                        .as_ref()
+                                        .unwrap()
+                                        .enter_game(h.clone(), room.code)
+                                        .await?;
+                                    h.add_peer(peer_slot.addr, peer_slot.name.clone(), handle).await??;
+                                }
+                                // Reconnection here
+                                GameContext::Roles(r) => {
+                                    reconnection!(r, peer_slot, reconnect_roles);
+                                }
+                                GameContext::Game(g) => {
+                                    reconnection!(g, peer_slot, reconnect_game);
+                                }
+                                s => unimplemented!("Connecting to state = {:?}"
+                                                    , GameContextKind::from(s)),
+                            };
+                            // !important; Disable the peer handle in Intro, but keep a peer_slot
+                            peer_slot.peer = None;
                         }
-                        s => unimplemented!("Connecting to state = {:?}"
-                                            , GameContextKind::from(s)),
                     };
-                    // !important; Disable the peer handle in Intro, but keep a peer_slot
-                    peer_slot.peer = None;
-                };
+                    Ok::<(), anyhow::Error>(())
+                }
+                .await
+                {
+                    error!(?sender, cause = %e, "Failed to enter a room");
+                }
             }
         }
     }
 }
 
-use tokio::sync::oneshot::error::RecvError;
-
-use super::details::actor_api;
+use super::details::{actor_api, ActorError};
 actor_api! { // Shared
     impl<M> Handle<Msg<SharedCmd, M>> {
-        pub async fn ping(&self) -> Result<(), RecvError>;
-        pub async fn get_chat_log(&self) -> Result<Vec<server::ChatLine>, RecvError>;
+        pub async fn ping(&self) -> Result<(), ActorError>;
+        pub async fn get_chat_log(&self) -> Result<Vec<server::ChatLine>, ActorError>;
         pub async fn append_chat(&self, line: server::ChatLine);
-        pub async fn get_peer_id_by_name(&self, username: Username) -> Result<Option<PlayerId>, RecvError>;
-        pub async fn get_peer_username(&self, whom: PlayerId) -> Result<Username, RecvError> ;
+        pub async fn get_peer_id_by_name(&self, username: Username) -> Result<Option<PlayerId>, ActorError>;
+        pub async fn get_peer_username(&self, whom: PlayerId) -> Result<Username, ActorError> ;
+        pub async fn get_player_count(&self) -> Result<usize, ActorError>;
         pub async fn drop_peer(&self, whom: PlayerId) ;
-        pub async fn shutdown(&self) -> Result<(), RecvError>;
+        pub async fn shutdown(&self) -> Result<(), ActorError>;
     }
 }
 
@@ -187,55 +209,74 @@ pub type GameHandle = Handle<Msg<SharedCmd, GameCmd>>;
 
 actor_api! { // Intro
     impl Handle<IntroCmd> {
-        pub async fn ping(&self) -> Result<(), RecvError>;
-        pub async fn login_player(&self, sender: SocketAddr, name: Username, handle: peer::IntroHandle) -> Result<LoginStatus, RecvError>;
-        pub async fn is_peer_connected(&self, who: PlayerId) -> Result<bool, RecvError>;
-        pub async fn enter_game(&self, who: PlayerId);
-        pub async fn get_chat_log(&self) -> Result<Option<Vec<server::ChatLine>>, RecvError>;
+        pub async fn ping(&self) -> Result<(), ActorError>;
+        pub async fn get_password_hash(&self, name: Username) -> Result<Option<String>, ActorError>;
+        pub async fn login_player(&self, sender: SocketAddr, name: Username, new_account: Option<String>, session: Option<SessionToken>, handle: peer::IntroHandle) -> Result<Result<SessionToken, LoginStatus>, ActorError>;
+        pub async fn is_peer_connected(&self, who: PlayerId) -> Result<bool, ActorError>;
+        pub async fn enter_game(&self, who: PlayerId, room: Option<RoomCode>);
+        pub async fn get_chat_log(&self, who: PlayerId) -> Result<Option<Vec<server::ChatLine>>, ActorError>;
+        pub async fn get_rooms(&self) -> Result<Vec<server::RoomInfo>, ActorError>;
+        pub async fn watch_room(&self, who: PlayerId, room: RoomCode);
+        pub async fn stop_watching(&self, who: PlayerId);
         pub async fn drop_peer(&self, whom: PlayerId) ;
-        pub async fn shutdown(&self) -> Result<(), RecvError>;
+        pub async fn shutdown(&self, reason: String) -> Result<(), ActorError>;
 
     }
 }
 
 actor_api! { // Home
     impl Handle<Msg<SharedCmd, HomeCmd>> {
-        pub async fn add_peer(&self, id: PlayerId, handle: peer::HomeHandle) -> Result<Result<(), PeersCapacityError>, RecvError>;
-        pub async fn broadcast(&self, sender: SocketAddr, message: Msg<SharedMsg, server::HomeMsg>) -> Result<(), RecvError>;
-        pub async fn broadcast_to_all(&self, msg:  Msg<SharedMsg, server::HomeMsg>) -> Result<(), RecvError> ;
+        pub async fn add_peer(&self, id: PlayerId, name: Username, handle: peer::HomeHandle) -> Result<Result<(), PeersCapacityError>, ActorError>;
+        pub async fn set_player_count(&self, sender: PlayerId, count: u8);
+        pub async fn broadcast(&self, sender: SocketAddr, message: Msg<SharedMsg, server::HomeMsg>) -> Result<(), ActorError>;
+        pub async fn broadcast_to_all(&self, msg:  Msg<SharedMsg, server::HomeMsg>) -> Result<(), ActorError> ;
         pub async fn start_roles(&self, sender: PlayerId);
+        pub async fn add_bot(&self, sender: PlayerId, server: HomeHandle);
+        pub async fn get_bot_count(&self) -> Result<usize, ActorError>;
+        pub async fn set_ready(&self, sender: PlayerId, ready: bool);
+        pub async fn kick(&self, sender: PlayerId, whom: Username);
+        pub async fn lock(&self, sender: PlayerId, locked: bool);
+        pub async fn is_locked(&self) -> Result<bool, ActorError>;
 
     }
 }
 
 actor_api! { // Roles
     impl Handle<Msg<SharedCmd, RolesCmd>> {
-        pub async fn get_available_roles(&self) -> Result<[RoleStatus; Role::count()], RecvError>;
+        pub async fn get_available_roles(&self) -> Result<[RoleStatus; Role::count()], ActorError>;
         pub async fn select_role(&self, sender: PlayerId, role: Role);
-        pub async fn broadcast(&self, sender: SocketAddr, message:  Msg<SharedMsg, server::RolesMsg>) -> Result<(), RecvError>;
-        pub async fn broadcast_to_all(&self, msg:  Msg<SharedMsg, server::RolesMsg>) -> Result<(), RecvError> ;
-        pub async fn is_peer_connected(&self, who: PlayerId) -> Result<bool, RecvError> ;
+        pub async fn broadcast(&self, sender: SocketAddr, message:  Msg<SharedMsg, server::RolesMsg>) -> Result<(), ActorError>;
+        pub async fn broadcast_to_all(&self, msg:  Msg<SharedMsg, server::RolesMsg>) -> Result<(), ActorError> ;
+        pub async fn is_peer_connected(&self, who: PlayerId) -> Result<bool, ActorError> ;
         pub async fn start_game(&self, sender: PlayerId);
-        pub async fn get_peer_handle_by_username(&self, whom: Username) -> Result<Option<PeerSlot<peer::RolesHandle>>, RecvError>;
-        pub async fn reconnect_peer(&self, whom: PlayerId, new: (PlayerId, peer::RolesHandle)) -> Result<(), RecvError> ;
+        pub async fn get_peer_handle_by_username(&self, whom: Username) -> Result<Option<PeerSlot<peer::RolesHandle>>, ActorError>;
+        pub async fn reconnect_peer(&self, whom: PlayerId, new: (PlayerId, peer::RolesHandle)) -> Result<Result<(), PeerNotFound>, ActorError> ;
     }
 }
 
 actor_api! { // Game
     impl Handle<Msg<SharedCmd, GameCmd>> {
-        pub async fn get_peer_handle_by_username(&self, whom: Username) -> Result<Option<PeerSlot<peer::GameHandle>>, RecvError>;
-        pub async fn is_peer_connected(&self, who: PlayerId) -> Result<bool, RecvError> ;
-        pub async fn broadcast(&self, sender: SocketAddr, message: Msg<SharedMsg, server::GameMsg>) -> Result<(), RecvError>;
-        pub async fn broadcast_to_all(&self, msg:  Msg<SharedMsg, server::GameMsg>) -> Result<(), RecvError> ;
-        pub async fn get_monsters(&self)          -> Result<[Option<Card>; 2], RecvError>;
-        pub async fn get_active_player(&self)     -> Result<PlayerId, RecvError>;
-        pub async fn get_game_phase(&self)        -> Result<GamePhaseKind, RecvError> ;
-        pub async fn drop_monster(&self, monster: Card) -> Result<Result<(), super::details::DeactivateItemError>, RecvError> ;
-        pub async fn switch_to_next_player(&self) -> Result<PlayerId, RecvError> ;
-        pub async fn next_monsters(&self)         -> Result<Result<(), super::details::EndOfItems>, RecvError>;
-        pub async fn continue_game_cycle(&self)   -> Result<(), RecvError>;
+        pub async fn get_peer_handle_by_username(&self, whom: Username) -> Result<Option<PeerSlot<peer::GameHandle>>, ActorError>;
+        pub async fn is_peer_connected(&self, who: PlayerId) -> Result<bool, ActorError> ;
+        pub async fn broadcast(&self, sender: SocketAddr, message: Msg<SharedMsg, server::GameMsg>) -> Result<(), ActorError>;
+        pub async fn broadcast_to_all(&self, msg:  Msg<SharedMsg, server::GameMsg>) -> Result<(), ActorError> ;
+        pub async fn get_monsters(&self)          -> Result<server::MonsterLine, ActorError>;
+        pub async fn get_active_player(&self)     -> Result<PlayerId, ActorError>;
+        pub async fn get_game_phase(&self)        -> Result<GamePhaseKind, ActorError> ;
+        pub async fn is_game_over(&self)          -> Result<bool, ActorError> ;
+        pub async fn hit_monster(&self, monster: Card) -> Result<Result<(), super::details::DeactivateItemError>, ActorError> ;
+        pub async fn swap_monster(&self, monster: Card) -> Result<Result<(), SwapMonsterError>, ActorError> ;
+        pub async fn switch_to_next_player(&self) -> Result<PlayerId, ActorError> ;
+        pub async fn next_monsters(&self)         -> Result<Result<(), super::details::EndOfItems>, ActorError>;
+        pub async fn continue_game_cycle(&self)   -> Result<(), ActorError>;
         pub async fn broadcast_game_state(&self, sender: PlayerId);
-        pub async fn reconnect_peer(&self, whom: PlayerId, new: (PlayerId, peer::GameHandle))  -> Result<(), RecvError> ;
+        pub async fn player_defeated(&self, whom: PlayerId);
+        pub async fn raise_shield(&self, paladin: PlayerId, shield: u16);
+        pub async fn sync_player(&self, whom: PlayerId, player: peer::Game);
+        pub async fn add_spectator(&self, id: PlayerId, name: Username, handle: peer::IntroHandle);
+        pub async fn remove_spectator(&self, id: PlayerId);
+        pub async fn reconnect_peer(&self, whom: PlayerId, new: (PlayerId, peer::GameHandle))  -> Result<Result<(), PeerNotFound>, ActorError> ;
+        pub async fn log_out_players(&self);
     }
 }
 
@@ -267,9 +308,26 @@ impl With<IntroCmd, IntroCmd> for Msg<SharedCmd, IntroCmd> {
     }
 }
 
+// runs a server task of the room and reports its exit to the Intro server.
+// A panic or an error ends only this room
+fn supervise(
+    code: RoomCode,
+    intro: Tx<(RoomCode, RoomEvent)>,
+    server: impl std::future::Future<Output = anyhow::Result<()>> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        match tokio::spawn(server).await {
+            Ok(Ok(())) => info!(room = code, "State server is done"),
+            Ok(Err(e)) => error!(room = code, "State server error = {:#}", e),
+            Err(e) => error!(room = code, cause = %e, "State server panicked"),
+        }
+        let _ = intro.send((code, RoomEvent::Stopped)).await;
+    });
+}
+
 async fn run_server(
     mut start_home: StartServer<HomeServer, Rx<Msg<SharedCmd, HomeCmd>>>,
-    intro: Tx<ServerHandleByContext>,
+    intro: RoomNotifier,
 ) -> anyhow::Result<()> {
     {
         let (cancel, cancel_rx) = oneshot::channel();
@@ -282,7 +340,7 @@ async fn run_server(
     }
 
     let mut start_roles =
-        StartServer::async_from(ServerConverter::new(start_home.server, &intro)).await;
+        StartServer::async_try_from(ServerConverter::new(start_home.server, &intro)).await?;
     {
         let (cancel, cancel_rx) = oneshot::channel();
         let state = ServerState::new(cancel);
@@ -292,17 +350,22 @@ async fn run_server(
                 .await?
         );
     }
-    let mut start_game =
-        StartServer::async_from(ServerConverter::new(start_roles.server, &intro)).await;
-    {
-        let (cancel, cancel_rx) = oneshot::channel();
-        let state = ServerState::new(cancel);
-        let _ = done!(
-            run_state(&mut start_game, state, cancel_rx)
-                .instrument(info_span!("Game"))
-                .await?
-        );
-    }
+    let start_game =
+        StartServer::async_try_from(ServerConverter::new(start_roles.server, &intro)).await?;
+    run_game(start_game).await
+}
+
+// the last state of a room, also runs games restored from snapshots
+async fn run_game(
+    mut start_game: StartServer<GameServer, Rx<Msg<SharedCmd, GameCmd>>>,
+) -> anyhow::Result<()> {
+    let (cancel, cancel_rx) = oneshot::channel();
+    let state = ServerState::new(cancel);
+    let _ = done!(
+        run_state(&mut start_game, state, cancel_rx)
+            .instrument(info_span!("Game"))
+            .await?
+    );
     Ok(())
 }
 
@@ -352,27 +415,140 @@ where
 }
 
 struct ServerHandleByContext(GameContext<(), HomeHandle, RolesHandle, GameHandle>);
+impl ServerHandleByContext {
+    // the task of the server has stopped
+    fn is_closed(&self) -> bool {
+        match &self.0 {
+            GameContext::Intro(()) => false,
+            GameContext::Home(h) => h.tx.is_closed(),
+            GameContext::Roles(r) => r.tx.is_closed(),
+            GameContext::Game(g) => g.tx.is_closed(),
+        }
+    }
+}
+
+// news of a room for the Intro server
+enum RoomEvent {
+    NewServer(ServerHandleByContext),
+    // the server task has exited, a room still open has crashed
+    Stopped,
+}
+
+// logged peers of all rooms
+const MAX_CONNECTION_COUNT: usize = MAX_ROOM_COUNT * MAX_PLAYER_COUNT;
+
 #[derive(Default)]
 pub struct IntroServer {
-    peers: Room<Option<peer::IntroHandle>>,
-    game_server: Option<ServerHandleByContext>,
+    peers: Room<Option<peer::IntroHandle>, MAX_CONNECTION_COUNT>,
+    rooms: ArrayVec<GameRoom, MAX_ROOM_COUNT>,
+    accounts: Accounts,
+    // the last session of each player
+    sessions: HashMap<String, SessionToken>,
+    // running games survive a restart only with snapshots
+    snapshots: Option<Snapshots>,
+}
+impl IntroServer {
+    pub fn new(accounts: Accounts, snapshots: Option<Snapshots>) -> Self {
+        IntroServer {
+            accounts,
+            sessions: snapshots
+                .as_ref()
+                .map(Snapshots::load_sessions)
+                .unwrap_or_default(),
+            snapshots,
+            ..Default::default()
+        }
+    }
+}
+
+// a chain of Home, Roles and Game servers
+struct GameRoom {
+    code: RoomCode,
+    host: Username,
+    server: ServerHandleByContext,
+    players: ArrayVec<PlayerId, MAX_PLAYER_COUNT>,
+    // spectators don't count against the player limit
+    spectators: Vec<PlayerId>,
+}
+
+// sends state changes of a room to the Intro server
+struct RoomNotifier {
+    code: RoomCode,
+    tx: Tx<(RoomCode, RoomEvent)>,
+    snapshots: Option<Snapshots>,
+}
+impl RoomNotifier {
+    async fn send(
+        &self,
+        server: ServerHandleByContext,
+    ) -> Result<(), mpsc::error::SendError<(RoomCode, RoomEvent)>> {
+        self.tx
+            .send((self.code, RoomEvent::NewServer(server)))
+            .await
+    }
+}
+
+// lines of a room for the chat log of new and reconnected players
+const MAX_CHAT_LINES: usize = 256;
+
+// the last lines of the chat, older ones are dropped
+#[derive(Debug, Default)]
+struct ChatLog(VecDeque<ChatLine>);
+impl ChatLog {
+    fn push(&mut self, line: ChatLine) {
+        if self.0.len() == MAX_CHAT_LINES {
+            self.0.pop_front();
+        }
+        self.0.push_back(line);
+    }
+    fn lines(&self) -> Vec<ChatLine> {
+        self.0.iter().cloned().collect()
+    }
+}
+impl From<Vec<ChatLine>> for ChatLog {
+    fn from(lines: Vec<ChatLine>) -> Self {
+        let skip = lines.len().saturating_sub(MAX_CHAT_LINES);
+        ChatLog(lines.into_iter().skip(skip).collect())
+    }
 }
 
 #[derive(Debug)]
 struct StateServer<T> {
-    chat: Vec<ChatLine>,
+    chat: ChatLog,
     peers: T,
 }
 
-type HomeServer = StateServer<Room<peer::HomeHandle>>;
+type HomeState = StateServer<Room<peer::HomeHandle>>;
+// a lobby of the room
+struct HomeServer {
+    state: HomeState,
+    ready: Vec<PlayerId>,
+    // new players can't enter a locked room
+    locked: bool,
+}
 type RolesServer = StateServer<Room<(PeerStatus, peer::RolesHandle)>>;
 
-use crate::protocol::server::MONSTERS_PER_LINE_COUNT;
 type GameState = StateServer<Stateble<Room<(PeerStatus, peer::GameHandle)>, 1>>;
 struct GameServer {
+    code: RoomCode,
     state: GameState,
-    monsters: Stateble<Deck, MONSTERS_PER_LINE_COUNT>,
+    monsters: Stateble<Deck, MAX_PLAYER_COUNT>,
     phase: GamePhaseKind,
+    // hits taken by bosses on the line
+    wounds: ArrayVec<(Card, u8), MAX_PLAYER_COUNT>,
+    defeated: ArrayVec<PlayerId, MAX_PLAYER_COUNT>,
+    // a Paladin shield for the next attacked teammate
+    shield: Option<(PlayerId, u16)>,
+    // in the order of peers
+    players: ArrayVec<server::PlayerView, MAX_PLAYER_COUNT>,
+    // the last known state of each peer for snapshots
+    peer_states: ArrayVec<Option<peer::Game>, MAX_PLAYER_COUNT>,
+    snapshots: Option<Snapshots>,
+    spectators: Vec<PeerSlot<peer::IntroHandle>>,
+    stats: server::GameStats,
+    outcome: Option<server::GameOutcome>,
+    // players leave the room once after the game is over
+    logged_out: bool,
 }
 
 impl StatebleItem for Room<(PeerStatus, peer::GameHandle)> {
@@ -402,7 +578,7 @@ impl StatebleItem for Deck {
 use crate::protocol::details::impl_GameContextKind_from_state;
 impl_GameContextKind_from_state! {IntroHandle => Intro, HomeHandle => Home, RolesHandle => Roles, GameHandle => Game,}
 impl_GameContextKind_from_state! {IntroServer => Intro, HomeServer => Home, RolesServer => Roles, GameServer => Game,}
-impl_GameContextKind_from_state! {StateServer<Stateble<Room<(PeerStatus, peer::GameHandle)>, 1>> => Game,}
+impl_GameContextKind_from_state! {StateServer<Room<peer::HomeHandle>> => Home, StateServer<Stateble<Room<(PeerStatus, peer::GameHandle)>, 1>> => Game,}
 
 impl IntroServer {
     #[tracing::instrument(skip_all, fields(p = %sender, name = %username))]
@@ -410,68 +586,253 @@ impl IntroServer {
         &mut self,
         sender: SocketAddr,
         username: Username,
+        new_account: Option<String>,
+        session: Option<SessionToken>,
         handle: peer::IntroHandle,
-    ) -> LoginStatus {
-        if self.peers.0.is_full() {
+    ) -> Result<SessionToken, LoginStatus> {
+        if self.peers.is_full() {
             info!("PlayerLimit");
-            return LoginStatus::PlayerLimit;
+            return Err(LoginStatus::PlayerLimit);
+        }
+        // the password is checked by the peer task, a new account comes with its hash
+        if let Some(hash) = new_account {
+            if let Err(e) = self.accounts.add(&username, hash) {
+                info!(cause = %e, "Account rejected");
+                return Err(LoginStatus::AccountExists);
+            }
         }
 
-        if futures::stream::iter(
-            self.peers
-                .0
-                .iter_mut()
-                .filter(|p| p.peer.is_some() && p.addr != sender),
-        )
-        .any(|p| async {
-            debug!(peer = %p.addr);
-            recv!(p.peer.as_ref().unwrap().get_username().await) == username
-        })
-        .instrument(tracing::debug_span!("LoginIntro"))
-        .await
+        if self
+            .peers
+            .0
+            .iter()
+            .any(|p| p.peer.is_some() && p.addr != sender && p.name == username)
         {
             info!("AlreadyLogged");
-            return LoginStatus::AlreadyLogged;
+            return Err(LoginStatus::AlreadyLogged);
         }
-        if self.game_server.is_some() {
-            macro_rules! is_logged_in {
-                ($server:expr) => {
-                    futures::future::OptionFuture::from(
-                        recv!($server.get_peer_id_by_name(username.clone()).await)
-                            .map(|p| async move { recv!($server.is_peer_connected(p).await) }),
-                    )
-                    .await
-                    .unwrap_or(false)
-                };
-            }
-            if let Err(status) = async {
-                debug!(server=?GameContextKind::from(&self.game_server.as_ref().unwrap().0));
-                if match &self.game_server.as_ref().unwrap().0 {
-                    GameContext::Home(h) => {
-                        recv!(h.get_peer_id_by_name(username.clone()).await).is_some()
+        macro_rules! rejoin_status {
+            ($server:expr) => {
+                // a stopped room has no seat to rejoin
+                match $server.get_peer_id_by_name(username.clone()).await {
+                    Err(_) | Ok(None) => Ok(None),
+                    Ok(Some(p)) if $server.is_peer_connected(p).await.unwrap_or(false) => {
+                        Err(LoginStatus::AlreadyLogged)
                     }
-                    GameContext::Roles(r) => {
-                        is_logged_in!(r)
+                    // a player of the started game, rejoin
+                    Ok(Some(p)) => Ok(Some(p)),
+                }
+            };
+        }
+        // look for the username across all rooms
+        let rejoin = async {
+            for (i, room) in self.rooms.iter().enumerate() {
+                debug!(room = room.code, server = ?GameContextKind::from(&room.server.0));
+                if let Some(old) = match &room.server.0 {
+                    GameContext::Home(h) => {
+                        if matches!(h.get_peer_id_by_name(username.clone()).await, Ok(Some(_))) {
+                            Err(LoginStatus::AlreadyLogged)
+                        } else {
+                            Ok(None)
+                        }
                     }
-                    GameContext::Game(g) => is_logged_in!(g),
+                    GameContext::Roles(r) => rejoin_status!(r),
+                    GameContext::Game(g) => rejoin_status!(g),
                     _ => unreachable!(),
-                } {
-                    Err(LoginStatus::AlreadyLogged)
-                } else {
-                    // Logged. fallthrough
-                    Ok(LoginStatus::Logged)
+                }? {
+                    return Ok(Some((i, old)));
                 }
             }
-            .instrument(tracing::debug_span!("LoginStateServer"))
-            .await
-            {
-                return status;
+            Ok::<_, LoginStatus>(None)
+        }
+        .instrument(tracing::debug_span!("LoginStateServer"))
+        .await;
+        let rejoin = rejoin.inspect_err(|status| info!(?status))?;
+        let token = match rejoin {
+            // a seat of a started game goes back only to the same session
+            Some(_) => match self.sessions.get(&*username) {
+                Some(token) if session.as_ref() == Some(token) => token.clone(),
+                _ => {
+                    info!("BadSession");
+                    return Err(LoginStatus::BadSession);
+                }
+            },
+            None => {
+                let token = SessionToken::generate();
+                self.sessions.insert(username.to_string(), token.clone());
+                if let Some(snapshots) = &self.snapshots {
+                    snapshots.save_sessions(self.sessions.clone());
+                }
+                token
+            }
+        };
+        if let Some((i, old)) = rejoin {
+            let room = &mut self.rooms[i];
+            debug!(room = room.code, "Rejoin");
+            // the old connection may be still in the room
+            match room.players.iter_mut().find(|p| **p == old) {
+                Some(p) => *p = sender,
+                None => room.players.try_push(sender).map_err(|_| {
+                    warn!(room = room.code, "No seat to rejoin");
+                    LoginStatus::PlayerLimit
+                })?,
             }
         }
-        handle.set_username(username).await;
-        self.peers.0.push(PeerSlot::new(sender, Some(handle)));
+        handle.set_username(username.clone()).await;
+        self.peers
+            .0
+            .push(PeerSlot::new(sender, username, Some(handle)));
         info!("Logged");
-        LoginStatus::Logged
+        Ok(token)
+    }
+
+    async fn enter_room(
+        &mut self,
+        sender: PlayerId,
+        code: Option<RoomCode>,
+    ) -> Result<(), EnterRoomError> {
+        // a rejoined player is already in the room
+        if self.rooms.iter().any(|r| r.players.contains(&sender)) {
+            return Ok(());
+        }
+        let code = match code {
+            Some(code) => code,
+            // a new room
+            None if self.rooms.is_full() => return Err(EnterRoomError::RoomLimit),
+            None => return Ok(()),
+        };
+        let room = self
+            .rooms
+            .iter_mut()
+            .find(|r| r.code == code)
+            .ok_or(EnterRoomError::NotFound)?;
+        match &room.server.0 {
+            GameContext::Home(h) => {
+                let count = h
+                    .get_player_count()
+                    .await
+                    .map_err(|_| EnterRoomError::NotFound)?;
+                let bots = h
+                    .get_bot_count()
+                    .await
+                    .map_err(|_| EnterRoomError::NotFound)?;
+                // bots take seats too
+                if room.players.len() + bots >= count {
+                    return Err(EnterRoomError::Full);
+                }
+                if h.is_locked().await.map_err(|_| EnterRoomError::NotFound)? {
+                    return Err(EnterRoomError::Locked);
+                }
+            }
+            _ => return Err(EnterRoomError::AlreadyStarted),
+        }
+        room.players
+            .try_push(sender)
+            .map_err(|_| EnterRoomError::Full)?;
+        Ok(())
+    }
+
+    async fn watch_room(&mut self, sender: PlayerId, code: RoomCode) -> Result<(), EnterRoomError> {
+        let peer = self
+            .peers
+            .get_peer(sender)
+            .map_err(|_| EnterRoomError::NotFound)?;
+        let (name, handle) = (peer.name.clone(), peer.get_peer_handle().clone());
+        let room = self
+            .rooms
+            .iter()
+            .position(|r| r.code == code)
+            .ok_or(EnterRoomError::NotFound)?;
+        match &self.rooms[room].server.0 {
+            GameContext::Game(_) => (),
+            _ => return Err(EnterRoomError::NotStarted),
+        }
+        self.stop_watching(sender).await;
+        let room = &mut self.rooms[room];
+        if let GameContext::Game(g) = &room.server.0 {
+            g.add_spectator(sender, name, handle).await;
+        }
+        room.spectators.push(sender);
+        Ok(())
+    }
+
+    async fn stop_watching(&mut self, sender: PlayerId) {
+        for room in self.rooms.iter_mut() {
+            if let Some(i) = room.spectators.iter().position(|s| *s == sender) {
+                room.spectators.swap_remove(i);
+                if let GameContext::Game(g) = &room.server.0 {
+                    g.remove_spectator(sender).await;
+                }
+            }
+        }
+    }
+
+    // players of restored games are offline until they log in again
+    fn restore_games(&mut self, notify: &Tx<(RoomCode, RoomEvent)>) {
+        let Some(snapshots) = self.snapshots.clone() else {
+            return;
+        };
+        for game in snapshots.load_games() {
+            if self.rooms.is_full() || self.rooms.iter().any(|r| r.code == game.code) {
+                warn!(room = game.code, "Skip a game snapshot");
+                continue;
+            }
+            self.restore_game(game, snapshots.clone(), notify);
+        }
+    }
+
+    fn restore_game(
+        &mut self,
+        game: GameSnapshot,
+        snapshots: Snapshots,
+        notify: &Tx<(RoomCode, RoomEvent)>,
+    ) {
+        let code = game.code;
+        let Some(host) = game.players.first().map(|(p, _)| p.name.clone()) else {
+            return;
+        };
+        let (tx, rx) = channel::<Msg<SharedCmd, GameCmd>>(MPSC_CHANNEL_CAPACITY);
+        let handle = GameHandle::for_tx(tx);
+        let server = GameServer::restore(game, handle.clone(), snapshots);
+        info!(room = code, "Restore a game");
+        self.rooms.push(GameRoom {
+            code,
+            host,
+            server: ServerHandleByContext::from(handle),
+            players: Default::default(),
+            spectators: Vec::new(),
+        });
+        supervise(code, notify.clone(), run_game(StartServer::new(server, rx)));
+    }
+
+    // the server of an open room has stopped. Its players are disconnected by
+    // their peers, a game goes on from the last snapshot
+    fn recover_room(&mut self, code: RoomCode, notify: &Tx<(RoomCode, RoomEvent)>) {
+        // a closed room stops its server itself
+        let Some(i) = self.rooms.iter().position(|r| r.code == code) else {
+            return;
+        };
+        let room = self.rooms.swap_remove(i);
+        warn!(room = code, server = ?GameContextKind::from(&room.server.0), "Room server has stopped");
+        let GameContext::Game(_) = &room.server.0 else {
+            return;
+        };
+        let Some(snapshots) = self.snapshots.clone() else {
+            return;
+        };
+        match snapshots.load_game(code) {
+            Some(game) => self.restore_game(game, snapshots, notify),
+            None => snapshots.remove(code),
+        }
+    }
+
+    fn new_room_code(&self) -> RoomCode {
+        loop {
+            let code = rand::thread_rng().gen_range(1000..10000);
+            if !self.rooms.iter().any(|r| r.code == code) {
+                return code;
+            }
+        }
     }
 }
 
@@ -491,10 +852,36 @@ impl<T> From<ChatLine> for Msg<server::SharedMsg, T> {
 impl ReceiveSharedCmd for GameServer {
     #[inline]
     async fn reduce_shared_cmd(&mut self, msg: SharedCmd) -> anyhow::Result<()> {
+        // the final state of a game for the next start
+        if let SharedCmd::Shutdown(_) = msg {
+            self.save_snapshot();
+            // the server may exit right after the answer
+            if let Some(snapshots) = &self.snapshots {
+                snapshots.flush().await;
+            }
+        }
         self.state.reduce_shared_cmd(msg).await
     }
 }
 
+#[async_trait::async_trait]
+impl ReceiveSharedCmd for HomeServer {
+    #[inline]
+    async fn reduce_shared_cmd(&mut self, msg: SharedCmd) -> anyhow::Result<()> {
+        let dropped = match &msg {
+            SharedCmd::DropPeer(id) => Some(*id),
+            _ => None,
+        };
+        self.state.reduce_shared_cmd(msg).await?;
+        // the next player becomes the host
+        if let Some(id) = dropped {
+            self.ready.retain(|p| *p != id);
+            self.broadcast_roster().await;
+        }
+        Ok(())
+    }
+}
+
 trait GetPeers {
     type Peer;
     fn peers(&self) -> &Room<Self::Peer>;
@@ -539,7 +926,7 @@ impl GetPeers for RolesServer {
         &mut self.peers
     }
 }
-impl GetPeers for HomeServer {
+impl GetPeers for HomeState {
     type Peer = Handle<Msg<peer::SharedCmd, peer::HomeCmd>>;
     #[inline]
     fn peers(&self) -> &Room<Self::Peer> {
@@ -550,12 +937,23 @@ impl GetPeers for HomeServer {
         &mut self.peers
     }
 }
+impl GetPeers for HomeServer {
+    type Peer = Handle<Msg<peer::SharedCmd, peer::HomeCmd>>;
+    #[inline]
+    fn peers(&self) -> &Room<Self::Peer> {
+        &self.state.peers
+    }
+    #[inline]
+    fn peers_mut(&mut self) -> &mut Room<Self::Peer> {
+        &mut self.state.peers
+    }
+}
 
 trait DropPeer {
     fn drop_peer(&mut self, whom: PlayerId) -> Result<(), PeerNotFound>;
 }
 
-impl DropPeer for HomeServer {
+impl DropPeer for HomeState {
     fn drop_peer(&mut self, whom: PlayerId) -> Result<(), PeerNotFound> {
         trace!("{} Drop handle in Home server", whom);
         let p = self
@@ -564,7 +962,8 @@ impl DropPeer for HomeServer {
             .iter()
             .position(|p| p.addr == whom)
             .ok_or(PeerNotFound(whom))?;
-        self.peers_mut().0.swap_pop(p);
+        // keeps the order of joining for the next host
+        self.peers_mut().0.remove(p);
         Ok(())
     }
 }
@@ -610,24 +1009,21 @@ where
                 let _ = tx.send(());
             }
             SharedCmd::GetPeerIdByName(name, tx) => {
-                let _ = tx.send({
-                    let mut id = None;
-                    for p in self.peers().0.iter() {
-                        if recv!(p.get_peer_handle().get_username().await) == name {
-                            id = Some(p.addr)
-                        }
-                    }
-                    id
-                });
+                let _ = tx.send(
+                    self.peers()
+                        .0
+                        .iter()
+                        .find(|p| p.name == name)
+                        .map(|p| p.addr),
+                );
             }
             SharedCmd::DropPeer(id) => {
                 if let Ok(p) = self.peers().get_peer(id) {
                     trace!("{} Drop a peer handle", id);
+                    let username = p.name.clone();
                     self.broadcast(
                         id,
-                        <StateServer<T> as Broadcast>::Msg::from(ChatLine::Disconnection(recv!(
-                            p.get_peer_handle().get_username().await
-                        ))),
+                        <StateServer<T> as Broadcast>::Msg::from(ChatLine::Disconnection(username)),
                     )
                     .await;
                     self.drop_peer(id).expect("Drop if peer is logged");
@@ -635,24 +1031,27 @@ where
             }
             SharedCmd::Shutdown(tx) => {
                 info!("Shutting down");
+                // nobody disconnects bots, their actors stop here
+                for p in self.peers().0.iter().filter(|p| bot::is_bot(&p.addr)) {
+                    p.get_peer_handle().close().await;
+                }
                 self.peers_mut().shutdown();
                 let _ = tx.send(());
             }
             SharedCmd::GetChatLog(tx) => {
-                let _ = tx.send(self.chat.clone());
+                let _ = tx.send(self.chat.lines());
+            }
+            SharedCmd::GetPlayerCount(tx) => {
+                let _ = tx.send(self.peers().player_count());
             }
             SharedCmd::AppendChat(line) => {
                 self.chat.push(line);
             }
             SharedCmd::GetPeerUsername(id, tx) => {
-                let _ = tx.send(recv!(
-                    self.peers()
-                        .get_peer(id)
-                        .unwrap()
-                        .get_peer_handle()
-                        .get_username()
-                        .await
-                ));
+                // an unknown peer drops the answer
+                if let Ok(p) = self.peers().get_peer(id) {
+                    let _ = tx.send(p.name.clone());
+                }
             }
         };
         Ok(())
@@ -661,30 +1060,33 @@ where
 
 pub struct ServerConverter<'a, S> {
     server: S,
-    intro: &'a mpsc::Sender<ServerHandleByContext>,
+    intro: &'a RoomNotifier,
 }
 impl<'a, S> ServerConverter<'a, S> {
     #[inline]
-    fn new(server: S, intro: &'a mpsc::Sender<ServerHandleByContext>) -> Self {
+    fn new(server: S, intro: &'a RoomNotifier) -> Self {
         ServerConverter { server, intro }
     }
 }
 
+// fails if the actor of a peer or of the Intro server is gone or stalled
 #[async_trait::async_trait]
-pub trait AsyncFrom<T> {
-    async fn async_from(value: T) -> Self
+pub trait AsyncTryFrom<T>: Sized {
+    async fn async_try_from(value: T) -> Result<Self, ActorError>
     where
         T: 'async_trait;
 }
 
 pub type SenderPeerId = PlayerId;
 #[async_trait::async_trait]
-impl<'a> AsyncFrom<(SenderPeerId, &'a mut IntroServer)>
+impl<'a> AsyncTryFrom<(SenderPeerId, RoomCode, &'a mut IntroServer)>
     for StartServer<HomeServer, Rx<Msg<SharedCmd, HomeCmd>>>
 where
-    (SenderPeerId, &'a mut IntroServer, Tx<ServerHandleByContext>): 'a,
+    (SenderPeerId, RoomCode, &'a mut IntroServer): 'a,
 {
-    async fn async_from((sender, intro): (SenderPeerId, &'a mut IntroServer)) -> Self {
+    async fn async_try_from(
+        (sender, code, intro): (SenderPeerId, RoomCode, &'a mut IntroServer),
+    ) -> Result<Self, ActorError> {
         let peer_slot = intro
             .peers
             .0
@@ -693,110 +1095,171 @@ where
             .expect("A peer in the Intro state");
         let (tx, rx) = channel::<Msg<SharedCmd, HomeCmd>>(MPSC_CHANNEL_CAPACITY);
         let home_handle = HomeHandle::for_tx(tx);
-        let peer_handle = recv!(
-            peer_slot
-                .peer
-                .as_ref()
-                .unwrap()
-                .enter_game(home_handle.clone())
-                .await
-        );
+        let host = peer_slot.name.clone();
+        let peer_handle = peer_slot
+            .peer
+            .as_ref()
+            .unwrap()
+            .enter_game(home_handle.clone(), code)
+            .await?;
         let mut server = HomeServer {
-            peers: Default::default(),
-            chat: Default::default(),
+            state: StateServer {
+                peers: Room(Default::default(), DEFAULT_PLAYER_COUNT),
+                chat: Default::default(),
+            },
+            ready: Vec::new(),
+            locked: false,
         };
-        server
-            .peers
-            .0
-            .push(PeerSlot::new(peer_slot.addr, peer_handle));
+        server.state.peers.0.push(PeerSlot::new(
+            peer_slot.addr,
+            host.clone(),
+            peer_handle.clone(),
+        ));
+        peer_handle
+            .send_tcp(Msg::with(server::HomeMsg::Roster(server::Roster {
+                players: vec![server::LobbyPlayer {
+                    name: host.clone(),
+                    ready: false,
+                }],
+                locked: false,
+            })))
+            .await;
         // peer moved to the home server
         peer_slot.peer = None;
-        intro.game_server = Some(ServerHandleByContext(GameContext::Home(home_handle)));
-        StartServer::new(server, rx)
+        intro.rooms.push(GameRoom {
+            code,
+            host,
+            server: ServerHandleByContext(GameContext::Home(home_handle)),
+            players: [sender].into_iter().collect(),
+            spectators: Vec::new(),
+        });
+        Ok(StartServer::new(server, rx))
     }
 }
 
 #[async_trait::async_trait]
-impl<'a> AsyncFrom<ServerConverter<'a, HomeServer>>
+impl<'a> AsyncTryFrom<ServerConverter<'a, HomeServer>>
     for StartServer<RolesServer, Rx<Msg<SharedCmd, RolesCmd>>>
 {
-    async fn async_from(home: ServerConverter<'a, HomeServer>) -> Self {
+    async fn async_try_from(home: ServerConverter<'a, HomeServer>) -> Result<Self, ActorError> {
         let (tx, rx) = channel::<Msg<SharedCmd, RolesCmd>>(MPSC_CHANNEL_CAPACITY);
         let handle = RolesHandle::for_tx(tx);
         home.intro
             .send(ServerHandleByContext::from(handle.clone()))
             .await
-            .expect("Must notify the Intro state");
+            .map_err(|_| ActorError::Gone)?;
+        // peers gone in the middle of the transition leave the room
         let peers: ArrayVec<PeerSlot<(PeerStatus, peer::RolesHandle)>, MAX_PLAYER_COUNT> =
-            futures::future::join_all(home.server.peers.0.iter().map(|p| async {
+            futures::future::join_all(home.server.state.peers.0.iter().map(|p| async {
                 let handle = handle.clone();
-                let peer_handle = recv!(p.peer.start_roles(handle).await);
-                PeerSlot::<(PeerStatus, peer::RolesHandle)> {
+                let peer_handle = p.peer.start_roles(handle).await.ok()?;
+                Some(PeerSlot::<(PeerStatus, peer::RolesHandle)> {
                     addr: p.addr,
+                    name: p.name.clone(),
                     peer: (PeerStatus::Online, peer_handle),
-                }
+                })
             }))
             .await
             .into_iter()
+            .flatten()
             .collect();
-        StartServer::new(
+        if peers.is_empty() {
+            return Err(ActorError::Gone);
+        }
+        Ok(StartServer::new(
             RolesServer {
-                chat: home.server.chat,
-                peers: Room::<(PeerStatus, peer::RolesHandle)>(peers),
+                chat: home.server.state.chat,
+                peers: Room::<(PeerStatus, peer::RolesHandle)>::with_peers(peers),
             },
             rx,
-        )
+        ))
     }
 }
 
 #[async_trait::async_trait]
-impl<'a> AsyncFrom<ServerConverter<'a, RolesServer>>
+impl<'a> AsyncTryFrom<ServerConverter<'a, RolesServer>>
     for StartServer<GameServer, Rx<Msg<SharedCmd, GameCmd>>>
 {
-    async fn async_from(mut roles: ServerConverter<'a, RolesServer>) -> Self {
+    async fn async_try_from(
+        mut roles: ServerConverter<'a, RolesServer>,
+    ) -> Result<Self, ActorError> {
         let (tx, rx) = channel::<Msg<SharedCmd, GameCmd>>(MPSC_CHANNEL_CAPACITY);
         let handle = GameHandle::for_tx(tx);
         roles
             .intro
             .send(ServerHandleByContext::from(handle.clone()))
             .await
-            .expect("Must notify the Intro state");
-        let peers: ArrayVec<PeerSlot<(PeerStatus, peer::GameHandle)>, MAX_PLAYER_COUNT> =
-            futures::future::join_all(roles.server.peers.0.iter_mut().map(|p| async {
-                let peer_handle = recv!(p.peer.1.start_game(handle.clone()).await);
+            .map_err(|_| ActorError::Gone)?;
+        // peers gone in the middle of the transition leave the game
+        let (peers, players): (
+            ArrayVec<PeerSlot<(PeerStatus, peer::GameHandle)>, MAX_PLAYER_COUNT>,
+            ArrayVec<server::PlayerView, MAX_PLAYER_COUNT>,
+        ) = futures::future::join_all(roles.server.peers.0.iter_mut().map(|p| async {
+            let player = server::PlayerView {
+                name: p.name.clone(),
+                role: p
+                    .peer
+                    .1
+                    .get_role()
+                    .await
+                    .ok()?
+                    .expect("Role must be selected"),
+                health: server::MAX_HEALTH,
+            };
+            let peer_handle = p.peer.1.start_game(handle.clone()).await.ok()?;
+            Some((
                 PeerSlot::<(PeerStatus, peer::GameHandle)> {
                     addr: p.addr,
+                    name: p.name.clone(),
                     peer: (PeerStatus::Online, peer_handle),
-                }
-            }))
-            .await
-            .into_iter()
-            .collect();
+                },
+                player,
+            ))
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .unzip();
+        if peers.is_empty() {
+            return Err(ActorError::Gone);
+        }
 
-        use crate::game::Deckable;
-        let mut monsters = Deck::default();
-        monsters.shuffle();
+        use crate::game::MonsterDeck;
+        let monsters = Deck::new_monster_deck();
+        let player_count = peers.len();
         let game_server = GameServer {
+            code: roles.intro.code,
             state: StateServer {
                 chat: roles.server.chat,
-                peers: Stateble::with_items(Room::<(PeerStatus, peer::GameHandle)>(peers)),
+                peers: Stateble::with_items(Room::<(PeerStatus, peer::GameHandle)>::with_peers(
+                    peers,
+                )),
             },
-            monsters: Stateble::<Deck, MONSTERS_PER_LINE_COUNT>::with_items(monsters),
+            monsters: Stateble::with_active_count(monsters, player_count),
             phase: GamePhaseKind::default(),
+            wounds: Default::default(),
+            defeated: Default::default(),
+            shield: None,
+            players,
+            peer_states: (0..player_count).map(|_| None).collect(),
+            snapshots: roles.intro.snapshots.clone(),
+            spectators: Default::default(),
+            stats: Default::default(),
+            outcome: None,
+            logged_out: false,
         };
 
         let p = game_server.state.peers.active_items()[0].expect("Always active one");
         use crate::protocol::TurnStatus;
-        recv!(
-            p.send_tcp(Msg::with(server::GameMsg::Turn(TurnStatus::Ready(
+        let _ = p
+            .send_tcp(Msg::with(server::GameMsg::Turn(TurnStatus::Ready(
                 game_server.phase,
             ))))
-            .await
-        );
+            .await;
         game_server
             .broadcast(p.addr, Msg::with(server::GameMsg::Turn(TurnStatus::Wait)))
             .await;
-        StartServer::new(game_server, rx)
+        Ok(StartServer::new(game_server, rx))
     }
 }
 
@@ -826,6 +1289,8 @@ enum PeerStatus {
 #[derive(Debug)]
 pub struct PeerSlot<T> {
     addr: PlayerId,
+    // kept by the server, it never asks a peer actor for it
+    name: Username,
     peer: T,
 }
 
@@ -857,9 +1322,10 @@ impl<T, S> GetPeerHandle for PeerSlot<(S, PeerHandle<T>)> {
 
 impl<T> PeerSlot<T> {
     #[inline]
-    fn new(addr: PlayerId, peer_handle: T) -> Self {
+    fn new(addr: PlayerId, name: Username, peer_handle: T) -> Self {
         PeerSlot {
             addr,
+            name,
             peer: peer_handle,
         }
     }
@@ -872,18 +1338,19 @@ where
     fn clone(&self) -> Self {
         PeerSlot {
             addr: self.addr,
+            name: self.name.clone(),
             peer: self.peer.clone(),
         }
     }
 }
 
+// peers and a count of players expected in the room
 #[derive(Debug)]
-#[repr(transparent)]
-struct Room<T>(pub ArrayVec<PeerSlot<T>, MAX_PLAYER_COUNT>);
+struct Room<T, const N: usize = MAX_PLAYER_COUNT>(pub ArrayVec<PeerSlot<T>, N>, usize);
 
-impl<T> Default for Room<T> {
+impl<T, const N: usize> Default for Room<T, N> {
     fn default() -> Self {
-        Room(Default::default())
+        Room(Default::default(), N)
     }
 }
 
@@ -895,7 +1362,29 @@ pub struct PeersCapacityError;
 #[error("Peer with addr {0} not found in the room")]
 pub struct PeerNotFound(pub PlayerId);
 
+#[derive(thiserror::Error, Debug)]
+pub enum SwapMonsterError {
+    #[error("Monster is not on the line")]
+    NotOnLine,
+    #[error("No unseen monster of the same kind to swap with")]
+    NothingToSwap,
+}
+
 impl<T> Room<T> {
+    fn with_peers(peers: ArrayVec<PeerSlot<T>, MAX_PLAYER_COUNT>) -> Self {
+        let count = peers.len();
+        Room(peers, count)
+    }
+}
+impl<T, const N: usize> Room<T, N> {
+    #[inline]
+    fn player_count(&self) -> usize {
+        self.1
+    }
+    #[inline]
+    fn is_full(&self) -> bool {
+        self.0.len() >= self.1
+    }
     #[inline]
     fn get_peer(&self, addr: SocketAddr) -> Result<&PeerSlot<T>, PeerNotFound> {
         self.0
@@ -904,7 +1393,7 @@ impl<T> Room<T> {
             .ok_or(PeerNotFound(addr))
     }
 }
-impl<T> Room<T> {
+impl<T, const N: usize> Room<T, N> {
     fn shutdown(&mut self) {
         trace!("Drop all peers");
         // if it is a last handle, peer actor will shutdown
@@ -936,14 +1425,47 @@ impl<'a> AsyncMessageReceiver<IntroCmd, &'a mut ServerState<IntroServer>> for In
             IntroCmd::Ping(tx) => {
                 let _ = tx.send(());
             }
-            IntroCmd::LoginPlayer(sender, username, handle, tx) => {
-                let _ = tx.send(self.login_player(sender, username, handle).await);
+            IntroCmd::GetPasswordHash(username, tx) => {
+                let _ = tx.send(self.accounts.password_hash(&username));
             }
-            IntroCmd::EnterGame(sender) => {
-                if let Err(e) = state.cancel.take().unwrap().send(sender) {
-                    error!(cause = %e, "Failed to cancel");
+            IntroCmd::LoginPlayer(sender, username, new_account, session, handle, tx) => {
+                let _ = tx.send(
+                    self.login_player(sender, username, new_account, session, handle)
+                        .await,
+                );
+            }
+            IntroCmd::EnterGame(sender, code) => match self.enter_room(sender, code).await {
+                Ok(()) => {
+                    self.stop_watching(sender).await;
+                    // a repeated request is already on the way
+                    if let Some(cancel) = state.cancel.take() {
+                        if let Err(e) = cancel.send(sender) {
+                            error!(cause = %e, "Failed to cancel");
+                        }
+                    }
+                }
+                Err(e) => {
+                    info!(?code, cause = ?e, "Room rejected");
+                    self.peers
+                        .get_peer(sender)?
+                        .get_peer_handle()
+                        .send_tcp(Msg::with(server::IntroMsg::RoomRejected(e)))
+                        .await;
+                }
+            },
+            IntroCmd::WatchRoom(sender, code) => {
+                if let Err(e) = self.watch_room(sender, code).await {
+                    info!(code, cause = ?e, "Watch rejected");
+                    self.peers
+                        .get_peer(sender)?
+                        .get_peer_handle()
+                        .send_tcp(Msg::with(server::IntroMsg::RoomRejected(e)))
+                        .await;
                 }
             }
+            IntroCmd::StopWatching(sender) => {
+                self.stop_watching(sender).await;
+            }
             IntroCmd::IsPeerConnected(sender, tx) => {
                 let _ = tx.send(self.peers.get_peer(sender).is_ok());
             }
@@ -953,36 +1475,97 @@ impl<'a> AsyncMessageReceiver<IntroCmd, &'a mut ServerState<IntroServer>> for In
                 if let Some(p) = self.peers.0.iter().position(|p| p.addr == id) {
                     self.peers.0.swap_pop(p);
                 }
-                // Drop state server if all peers disconnected
-                if self.peers.0.is_empty() {
-                    if let Some(s) = &self.game_server {
-                        match &s.0 {
-                            GameContext::Home(h) => recv!(h.shutdown().await),
-                            GameContext::Roles(r) => recv!(r.shutdown().await),
-                            GameContext::Game(h) => recv!(h.shutdown().await),
+                self.stop_watching(id).await;
+                if let Some(i) = self.rooms.iter().position(|r| r.players.contains(&id)) {
+                    self.rooms[i].players.retain(|p| *p != id);
+                    // Drop state server if all peers of the room disconnected,
+                    // a stopped server is left to the supervisor
+                    if self.rooms[i].players.is_empty() && !self.rooms[i].server.is_closed() {
+                        let room = self.rooms.swap_remove(i);
+                        info!(room = room.code, "Close the room");
+                        // a gone server is already stopped
+                        match &room.server.0 {
+                            GameContext::Home(h) => {
+                                let _ = h.shutdown().await;
+                            }
+                            GameContext::Roles(r) => {
+                                let _ = r.shutdown().await;
+                            }
+                            GameContext::Game(h) => {
+                                let _ = h.shutdown().await;
+                                if let Some(snapshots) = &self.snapshots {
+                                    snapshots.remove(room.code);
+                                }
+                            }
                             _ => unreachable!(),
                         }
                     }
-                    self.game_server = None;
                 }
             }
-            IntroCmd::Shutdown(tx) => {
-                info!("Shutting down");
+            IntroCmd::Shutdown(reason, tx) => {
+                info!(%reason, "Shutting down");
+                // peers close their connections after this message
+                let msg = server::SharedMsg::ServerShutdown { reason };
+                for p in self.peers.0.iter() {
+                    if let Some(peer) = &p.peer {
+                        peer.send_tcp(Msg::Shared(msg.clone())).await;
+                    }
+                }
+                for room in self.rooms.iter() {
+                    match &room.server.0 {
+                        GameContext::Home(h) => {
+                            let _ = h.broadcast_to_all(Msg::Shared(msg.clone())).await;
+                            let _ = h.shutdown().await;
+                        }
+                        GameContext::Roles(r) => {
+                            let _ = r.broadcast_to_all(Msg::Shared(msg.clone())).await;
+                            let _ = r.shutdown().await;
+                        }
+                        GameContext::Game(g) => {
+                            let _ = g.broadcast_to_all(Msg::Shared(msg.clone())).await;
+                            let _ = g.shutdown().await;
+                        }
+                        _ => unreachable!(),
+                    }
+                }
                 self.peers.shutdown();
-                self.game_server = None;
+                self.rooms.clear();
                 let _ = tx.send(());
             }
-            IntroCmd::GetChatLog(tx) => {
+            IntroCmd::GetRooms(tx) => {
+                let mut rooms = Vec::with_capacity(self.rooms.len());
+                for room in self.rooms.iter() {
+                    let player_count = match &room.server.0 {
+                        GameContext::Home(h) => h.get_player_count().await,
+                        GameContext::Roles(r) => r.get_player_count().await,
+                        GameContext::Game(g) => g.get_player_count().await,
+                        _ => unreachable!(),
+                    };
+                    // skip a room with a stopped server
+                    let Ok(player_count) = player_count else {
+                        continue;
+                    };
+                    rooms.push(server::RoomInfo {
+                        code: room.code,
+                        state: GameContextKind::from(&room.server.0),
+                        host: room.host.clone(),
+                        players: room.players.len() as u8,
+                        player_count: player_count as u8,
+                    });
+                }
+                let _ = tx.send(rooms);
+            }
+            IntroCmd::GetChatLog(sender, tx) => {
                 _ = tx.send({
-                    if self.game_server.is_some() {
-                        Some(match &self.game_server.as_ref().unwrap().0 {
-                            GameContext::Home(h) => recv!(h.get_chat_log().await),
-                            GameContext::Roles(r) => recv!(r.get_chat_log().await),
-                            GameContext::Game(g) => recv!(g.get_chat_log().await),
+                    match self.rooms.iter().find(|r| r.players.contains(&sender)) {
+                        Some(room) => match &room.server.0 {
+                            GameContext::Home(h) => h.get_chat_log().await,
+                            GameContext::Roles(r) => r.get_chat_log().await,
+                            GameContext::Game(g) => g.get_chat_log().await,
                             _ => unreachable!(),
-                        })
-                    } else {
-                        None
+                        }
+                        .ok(),
+                        None => None,
                     }
                 });
             }
@@ -991,6 +1574,60 @@ impl<'a> AsyncMessageReceiver<IntroCmd, &'a mut ServerState<IntroServer>> for In
     }
 }
 
+impl HomeServer {
+    // the first player in the lobby, bots never host
+    fn host(&self) -> Option<PlayerId> {
+        self.state
+            .peers
+            .0
+            .iter()
+            .find(|p| !bot::is_bot(&p.addr))
+            .map(|p| p.addr)
+    }
+
+    async fn roster(&self) -> server::Roster {
+        let host = self.host();
+        let mut peers: Vec<_> = self.state.peers.0.iter().collect();
+        peers.sort_by_key(|p| Some(p.addr) != host);
+        let mut players = Vec::with_capacity(peers.len());
+        for p in peers {
+            players.push(server::LobbyPlayer {
+                name: p.name.clone(),
+                ready: self.ready.contains(&p.addr),
+            });
+        }
+        server::Roster {
+            players,
+            locked: self.locked,
+        }
+    }
+
+    async fn broadcast_roster(&self) {
+        let roster = self.roster().await;
+        self.broadcast_to_all(Msg::with(server::HomeMsg::Roster(roster)))
+            .await;
+    }
+
+    fn check_start(&self, sender: PlayerId) -> Result<(), server::StartRolesError> {
+        use server::StartRolesError;
+        if self.host() != Some(sender) {
+            Err(StartRolesError::HostOnly)
+        } else if !self.state.peers.is_full() {
+            Err(StartRolesError::NotFull)
+        } else if !self
+            .state
+            .peers
+            .0
+            .iter()
+            .all(|p| self.ready.contains(&p.addr))
+        {
+            Err(StartRolesError::NotReady)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl<'a> AsyncMessageReceiver<HomeCmd, &'a mut ServerState<HomeServer>> for HomeServer {
     async fn reduce(
@@ -999,13 +1636,41 @@ impl<'a> AsyncMessageReceiver<HomeCmd, &'a mut ServerState<HomeServer>> for Home
         state: &'a mut ServerState<HomeServer>,
     ) -> anyhow::Result<()> {
         match msg {
-            HomeCmd::AddPeer(id, peer, tx) => {
-                let _ = tx.send(
-                    self.peers
-                        .0
-                        .try_push(PeerSlot::new(id, peer))
-                        .map_err(|_| PeersCapacityError),
-                );
+            HomeCmd::AddPeer(id, name, peer, tx) => {
+                peer.send_tcp(Msg::with(server::HomeMsg::PlayerCount(
+                    self.state.peers.player_count() as u8,
+                )))
+                .await;
+                let added = self
+                    .state
+                    .peers
+                    .0
+                    .try_push(PeerSlot::new(id, name, peer))
+                    .map_err(|_| PeersCapacityError);
+                let _ = tx.send(added);
+                self.broadcast_roster().await;
+            }
+            HomeCmd::SetPlayerCount(sender, count) => {
+                let count = count as usize;
+                // only the host sets it, and can't leave behind players in the lobby
+                if self.host() == Some(sender)
+                    && (1..=MAX_PLAYER_COUNT).contains(&count)
+                    && count >= self.state.peers.0.len()
+                {
+                    self.state.peers.1 = count;
+                    info!(count, "Player count");
+                    self.broadcast_to_all(Msg::with(server::HomeMsg::PlayerCount(count as u8)))
+                        .await;
+                } else {
+                    self.state
+                        .peers
+                        .get_peer(sender)?
+                        .peer
+                        .send_tcp(Msg::with(server::HomeMsg::PlayerCount(
+                            self.state.peers.player_count() as u8,
+                        )))
+                        .await;
+                }
             }
             HomeCmd::Broadcast(sender, msg, tx) => {
                 self.broadcast(sender, msg).await;
@@ -1015,11 +1680,98 @@ impl<'a> AsyncMessageReceiver<HomeCmd, &'a mut ServerState<HomeServer>> for Home
                 self.broadcast_to_all(msg).await;
                 let _ = tx.send(());
             }
-            HomeCmd::StartRoles(sender) => {
-                if self.peers.0.is_full() {
-                    state.cancel.take().unwrap().send(sender).expect("Done");
+            HomeCmd::AddBot(sender, server) => {
+                if self.state.peers.is_full() || self.host() != Some(sender) {
+                    return Ok(());
+                }
+                let (addr, username) = bot::new_bot();
+                info!(?addr, %username, "Add a bot");
+                let peer = bot::join_home(addr, username.clone(), server, Arc::new(bot::Greedy));
+                self.state
+                    .peers
+                    .0
+                    .push(PeerSlot::new(addr, username.clone(), peer));
+                // bots are always ready
+                self.ready.push(addr);
+                let line = ChatLine::Connection(username);
+                self.state.chat.push(line.clone());
+                self.broadcast_to_all(Msg::from(line)).await;
+                self.broadcast_roster().await;
+            }
+            HomeCmd::GetBotCount(tx) => {
+                let _ = tx.send(
+                    self.state
+                        .peers
+                        .0
+                        .iter()
+                        .filter(|p| bot::is_bot(&p.addr))
+                        .count(),
+                );
+            }
+            HomeCmd::SetReady(sender, ready) => {
+                self.state.peers.get_peer(sender)?;
+                self.ready.retain(|p| *p != sender);
+                if ready {
+                    self.ready.push(sender);
+                }
+                self.broadcast_roster().await;
+            }
+            HomeCmd::Kick(sender, whom) => {
+                if self.host() != Some(sender) {
+                    return Ok(());
+                }
+                let kicked = self
+                    .state
+                    .peers
+                    .0
+                    .iter()
+                    .find(|p| p.addr != sender && p.name == whom)
+                    .map(|p| p.addr);
+                let Some(addr) = kicked else {
+                    return Ok(());
+                };
+                info!(?addr, %whom, "Kick a player");
+                let peer = self.state.peers.get_peer(addr)?.peer.clone();
+                self.state.drop_peer(addr)?;
+                self.ready.retain(|p| *p != addr);
+                if bot::is_bot(&addr) {
+                    peer.close().await;
+                } else {
+                    // the peer closes the connection after it
+                    peer.send_tcp(Msg::Shared(SharedMsg::Kicked)).await;
+                }
+                let line = ChatLine::GameEvent(format!("{} has been kicked", whom));
+                self.state.chat.push(line.clone());
+                self.broadcast_to_all(Msg::from(line)).await;
+                self.broadcast_roster().await;
+            }
+            HomeCmd::Lock(sender, locked) => {
+                if self.host() == Some(sender) {
+                    info!(locked, "Lock the room");
+                    self.locked = locked;
+                    self.broadcast_roster().await;
                 }
             }
+            HomeCmd::IsLocked(tx) => {
+                let _ = tx.send(self.locked);
+            }
+            HomeCmd::StartRoles(sender) => match self.check_start(sender) {
+                Ok(()) => {
+                    // the room is already starting after a repeated request
+                    if let Some(cancel) = state.cancel.take() {
+                        let _ = cancel.send(sender);
+                    }
+                }
+                Err(reason) => {
+                    debug!(?sender, ?reason, "Start rejected");
+                    self.state
+                        .peers
+                        .get_peer(sender)?
+                        .peer
+                        .send_tcp(Msg::with(server::HomeMsg::StartRejected(reason)))
+                        .await;
+                }
+            },
         };
         Ok(())
     }
@@ -1027,17 +1779,11 @@ impl<'a> AsyncMessageReceiver<HomeCmd, &'a mut ServerState<HomeServer>> for Home
 
 macro_rules! get_peer_by_name {
     ($iterable:expr, $name:expr) => {{
-        let mut peer = None;
-        for p in $iterable {
-            if recv!(p.peer.1.get_username().await) == $name {
-                peer = Some(PeerSlot {
-                    addr: p.addr,
-                    peer: p.peer.1.clone(),
-                });
-                break;
-            }
-        }
-        peer
+        $iterable.find(|p| p.name == $name).map(|p| PeerSlot {
+            addr: p.addr,
+            name: p.name.clone(),
+            peer: p.peer.1.clone(),
+        })
     }};
 }
 
@@ -1058,7 +1804,14 @@ impl<'a> AsyncMessageReceiver<RolesCmd, &'a mut ServerState<RolesServer>> for Ro
                 let _ = tx.send(());
             }
             RolesCmd::SelectRole(sender, role) => {
-                let status = self.set_role_for_peer(sender, role).await;
+                let status = match self.set_role_for_peer(sender, role).await {
+                    Ok(status) => status,
+                    Err(cause) => {
+                        warn!(?sender, %cause, "Mark a peer offline");
+                        let _ = self.drop_peer(sender);
+                        return Ok(());
+                    }
+                };
                 debug!(?sender, ?role, ?status, "Select");
                 let peer = self.peers.get_peer(sender).expect("Must exists");
                 peer.peer
@@ -1066,10 +1819,11 @@ impl<'a> AsyncMessageReceiver<RolesCmd, &'a mut ServerState<RolesServer>> for Ro
                     .send_tcp(Msg::with(server::RolesMsg::SelectedStatus(status)))
                     .await;
                 if let Ok(r) = &status {
+                    let username = peer.name.clone();
                     self.broadcast(
                         sender,
                         Msg::with(server::SharedMsg::Chat(server::ChatLine::GameEvent(
-                            format!("{} select {:?}", recv!(peer.peer.1.get_username().await), r),
+                            format!("{} select {:?}", username, r),
                         ))),
                     )
                     .await;
@@ -1095,34 +1849,26 @@ impl<'a> AsyncMessageReceiver<RolesCmd, &'a mut ServerState<RolesServer>> for Ro
             }
             RolesCmd::StartGame(sender) => {
                 if self.are_all_have_roles().await {
-                    state
-                        .cancel
-                        .take()
-                        .unwrap()
-                        .send(sender)
-                        .expect("Must not be dropped");
+                    // the game is already starting after a repeated request
+                    if let Some(cancel) = state.cancel.take() {
+                        let _ = cancel.send(sender);
+                    }
                 }
                 // TODO else return Status
             }
             RolesCmd::ReconnectPeer(whom, (addr, peer), tx) => {
                 let roles = self.collect_roles().await;
-                let p = self
-                    .peers_mut()
-                    .0
-                    .iter_mut()
-                    .find(|p| p.addr == whom)
-                    .ok_or(PeerNotFound(whom))
-                    .expect("Reconnect only existing peer");
-                *p = PeerSlot {
-                    addr,
-                    peer: (PeerStatus::Online, peer),
+                let Some(p) = self.peers_mut().0.iter_mut().find(|p| p.addr == whom) else {
+                    let _ = tx.send(Err(PeerNotFound(whom)));
+                    return Ok(());
                 };
-                recv!(
-                    p.send_tcp(Msg::with(server::RolesMsg::AvailableRoles(roles)))
-                        .await
-                );
+                p.addr = addr;
+                p.peer = (PeerStatus::Online, peer);
+                let _ = p
+                    .send_tcp(Msg::with(server::RolesMsg::AvailableRoles(roles)))
+                    .await;
                 debug!(all_peers = ?self.peers().0);
-                let _ = tx.send(());
+                let _ = tx.send(Ok(()));
             }
         }
         Ok(())
@@ -1137,10 +1883,16 @@ impl<'a> AsyncMessageReceiver<GameCmd, &'a mut ServerState<GameServer>> for Game
     ) -> anyhow::Result<()> {
         match msg {
             GameCmd::Broadcast(sender, msg, tx) => {
+                if let Msg::Shared(SharedMsg::Chat(line)) = &msg {
+                    self.broadcast_to_spectators(Msg::from(line.clone())).await;
+                }
                 self.broadcast(sender, msg).await;
                 let _ = tx.send(());
             }
             GameCmd::BroadcastToAll(msg, tx) => {
+                if let Msg::Shared(SharedMsg::Chat(line)) = &msg {
+                    self.broadcast_to_spectators(Msg::from(line.clone())).await;
+                }
                 self.broadcast_to_all(msg).await;
                 let _ = tx.send(());
             }
@@ -1158,49 +1910,49 @@ impl<'a> AsyncMessageReceiver<GameCmd, &'a mut ServerState<GameServer>> for Game
                 );
             }
             GameCmd::GetMonsters(tx) => {
-                let _ = tx.send(self.monsters.active_items().map(|i| i.copied()));
+                let _ = tx.send(
+                    self.monsters
+                        .active_items()
+                        .into_iter()
+                        .map(|i| i.copied())
+                        .collect(),
+                );
             }
 
             GameCmd::GetActivePlayer(tx) => {
-                let _ = tx.send(
-                    self.state.peers.active_items()[0]
-                        .as_ref()
-                        .expect("Always have one active player")
-                        .addr,
-                );
+                let _ = tx.send(self.active_player());
             }
             GameCmd::GetGamePhase(tx) => {
                 let _ = tx.send(self.phase);
             }
-            GameCmd::DropMonster(monster, tx) => {
-                let _ = tx.send(self.monsters.deactivate_item(&monster));
+            GameCmd::IsGameOver(tx) => {
+                let _ = tx.send(self.outcome.is_some());
+            }
+            GameCmd::LogOutPlayers() => {
+                if self.outcome.is_some() && !self.logged_out {
+                    self.logged_out = true;
+                    // players leave the room after the final board,
+                    // the Intro server closes it after the last one
+                    futures::stream::iter(self.peers().0.iter())
+                        .for_each_concurrent(MAX_PLAYER_COUNT, |p| async {
+                            let _ = p.peer.1.leave_game().await;
+                        })
+                        .await;
+                }
+            }
+            GameCmd::HitMonster(monster, tx) => {
+                let _ = tx.send(self.hit_monster(monster).await);
+            }
+            GameCmd::SwapMonster(monster, tx) => {
+                let _ = tx.send(self.swap_monster(monster).await);
             }
             GameCmd::SwitchToNextPlayer(tx) => {
-                let next_player = self.switch_to_next_player();
-                use crate::protocol::TurnStatus;
-                self.broadcast(
-                    next_player,
-                    Msg::with(server::GameMsg::Turn(TurnStatus::Wait)),
-                )
-                .await;
-                recv!(
-                    self.state
-                        .peers
-                        .items
-                        .get_peer(next_player)
-                        .expect("Must exists")
-                        .send_tcp(Msg::with(server::GameMsg::Turn(TurnStatus::Ready(
-                            self.phase,
-                        ))))
-                        .await
-                );
-                let _ = tx.send(next_player);
+                let _ = tx.send(self.next_turn().await);
             }
             GameCmd::NextMonsters(tx) => {
                 let _ = tx.send(self.monsters.next_actives());
             }
             GameCmd::ContinueGameCycle(tx) => {
-                // TODO end of game here
                 let _ = tx.send(());
             }
             GameCmd::BroadcastGameState(sender) => {
@@ -1211,18 +1963,74 @@ impl<'a> AsyncMessageReceiver<GameCmd, &'a mut ServerState<GameServer>> for Game
                     .await;
             }
             GameCmd::ReconnectPeer(whom, (addr, peer), tx) => {
-                let p = self
-                    .peers_mut()
-                    .0
-                    .iter_mut()
-                    .find(|p| p.addr == whom)
-                    .ok_or(PeerNotFound(whom))
-                    .expect("Remove existing peer");
-                *p = PeerSlot {
-                    addr,
-                    peer: (PeerStatus::Online, peer),
+                let Some(p) = self.peers_mut().0.iter_mut().find(|p| p.addr == whom) else {
+                    let _ = tx.send(Err(PeerNotFound(whom)));
+                    return Ok(());
                 };
-                let _ = tx.send(());
+                p.addr = addr;
+                p.peer = (PeerStatus::Online, peer);
+                if let Some(d) = self.defeated.iter_mut().find(|d| **d == whom) {
+                    *d = addr;
+                }
+                if let Some((paladin, _)) = self.shield.as_mut().filter(|(p, _)| *p == whom) {
+                    *paladin = addr;
+                }
+                // the turn may have passed while the peer was offline
+                if self.outcome.is_none() {
+                    use crate::protocol::TurnStatus;
+                    let turn = if self.active_player() == addr {
+                        TurnStatus::Ready(self.phase)
+                    } else {
+                        TurnStatus::Wait
+                    };
+                    let _ = self
+                        .peers()
+                        .get_peer(addr)
+                        .expect("Reconnected")
+                        .send_tcp(Msg::with(server::GameMsg::Turn(turn)))
+                        .await;
+                }
+                let _ = tx.send(Ok(()));
+            }
+            GameCmd::PlayerDefeated(whom) => {
+                if !self.defeated.contains(&whom) {
+                    self.defeated.push(whom);
+                }
+                if self.outcome.is_none() && self.defeated.len() == self.peers().0.len() {
+                    self.game_over(server::GameOutcome::Defeat).await;
+                }
+            }
+            GameCmd::RaiseShield(paladin, shield) => {
+                self.shield = Some((paladin, shield));
+                self.game_event(ChatLine::GameEvent(format!(
+                    "A shield of {} protects the next attacked teammate",
+                    shield
+                )))
+                .await;
+            }
+            GameCmd::SyncPlayer(whom, player) => {
+                if let Some(i) = self.peers().0.iter().position(|p| p.addr == whom) {
+                    self.players[i].health = player.health;
+                    // a new game is saved once the states of all players are known
+                    if self.peer_states[i].replace(player).is_none() {
+                        self.save_snapshot();
+                    }
+                }
+                self.broadcast_to_spectators(Msg::with(server::IntroMsg::Watch(self.game_view())))
+                    .await;
+            }
+            GameCmd::AddSpectator(id, name, handle) => {
+                info!(spectator = ?id, "Add a spectator");
+                handle
+                    .send_tcp(Msg::with(SharedMsg::ChatLog(self.state.chat.lines())))
+                    .await;
+                handle
+                    .send_tcp(Msg::with(server::IntroMsg::Watch(self.game_view())))
+                    .await;
+                self.spectators.push(PeerSlot::new(id, name, handle));
+            }
+            GameCmd::RemoveSpectator(id) => {
+                self.spectators.retain(|s| s.addr != id);
             }
         };
 
@@ -1239,7 +2047,8 @@ impl RolesServer {
             futures::future::join_all(self.peers.0.iter().map(|p| p.peer.1.get_role()))
                 .await
                 .into_iter()
-                .map(|p| recv!(p))
+                // a gone peer holds no role
+                .map(|p| p.ok().flatten())
                 .collect::<Vec<_>>();
         Role::all().map(
             |r| match peer_roles.iter().any(|p| p.is_some_and(|pr| pr == r)) {
@@ -1252,28 +2061,306 @@ impl RolesServer {
         &self,
         sender: PlayerId,
         role: Role,
-    ) -> Result<Role, SelectRoleError> {
+    ) -> Result<Result<Role, SelectRoleError>, ActorError> {
         for p in self.peers.0.iter() {
-            if recv!(p.peer.1.get_role().await).is_some_and(|r| r == role) {
-                return Err(if p.addr != sender {
+            if matches!(p.peer.1.get_role().await, Ok(Some(r)) if r == role) {
+                return Ok(Err(if p.addr != sender {
                     SelectRoleError::Busy
                 } else {
                     SelectRoleError::AlreadySelected
-                });
+                }));
             }
         }
         let p = &self.peers.get_peer(sender).expect("Must exists").peer;
-        recv!(p.1.select_role(role).await);
-        Ok(role)
+        p.1.select_role(role).await?;
+        Ok(Ok(role))
     }
     async fn are_all_have_roles(&self) -> bool {
         futures::stream::iter(self.peers.0.iter())
-            .all(|p| async move { recv!(p.peer.1.get_role().await).is_some() })
+            .all(|p| async move { matches!(p.peer.1.get_role().await, Ok(Some(_))) })
             .await
     }
 }
 
 impl GameServer {
+    async fn next_turn(&mut self) -> PlayerId {
+        if self.outcome.is_some() {
+            return self.active_player();
+        }
+        let mut next_player = self.switch_to_next_player();
+        // defeated players skip their turns
+        while self.outcome.is_none()
+            && self.defeated.contains(&next_player)
+            && self.defeated.len() < self.peers().0.len()
+        {
+            next_player = self.switch_to_next_player();
+        }
+        if let Some(outcome) = self.outcome {
+            self.game_over(outcome).await;
+            return next_player;
+        }
+        use crate::protocol::TurnStatus;
+        self.broadcast(
+            next_player,
+            Msg::with(server::GameMsg::Turn(TurnStatus::Wait)),
+        )
+        .await;
+        let _ = self
+            .state
+            .peers
+            .items
+            .get_peer(next_player)
+            .expect("Must exists")
+            .send_tcp(Msg::with(server::GameMsg::Turn(TurnStatus::Ready(
+                self.phase,
+            ))))
+            .await;
+        if self.phase == GamePhaseKind::Defend {
+            self.attack_defender(next_player).await;
+        }
+        // the active player syncs before the end of the turn
+        self.save_snapshot();
+        next_player
+    }
+    async fn hit_monster(&mut self, monster: Card) -> Result<(), DeactivateItemError> {
+        if !self
+            .monsters
+            .active_items()
+            .iter()
+            .flatten()
+            .any(|m| **m == monster)
+        {
+            return Err(DeactivateItemError::AlreadyNotActive);
+        }
+        let hits = match self.wounds.iter_mut().find(|(m, _)| *m == monster) {
+            Some((_, hits)) => {
+                *hits += 1;
+                *hits
+            }
+            None => {
+                self.wounds.push((monster, 1));
+                1
+            }
+        };
+        if hits < monster.hits() {
+            self.game_event(ChatLine::GameEvent(format!(
+                "{:?} is wounded {}/{}",
+                monster,
+                hits,
+                monster.hits()
+            )))
+            .await;
+            return Ok(());
+        }
+        self.wounds.retain(|(m, _)| *m != monster);
+        self.monsters.deactivate_item(&monster)?;
+        self.stats.monsters_defeated += 1;
+        Ok(())
+    }
+    async fn swap_monster(&mut self, monster: Card) -> Result<(), SwapMonsterError> {
+        let i = self
+            .monsters
+            .actives
+            .iter()
+            .filter_map(|a| match a {
+                ActiveState::Enable(i) => Some(*i),
+                ActiveState::Disable(_) => None,
+            })
+            .find(|i| self.monsters.items.cards[*i] == monster)
+            .ok_or(SwapMonsterError::NotOnLine)?;
+        // cards after the monster line are not shown yet
+        let unseen = self
+            .monsters
+            .actives
+            .iter()
+            .map(|a| a.unwrap_index())
+            .max()
+            .expect("Not empty")
+            + 1;
+        let new = self
+            .monsters
+            .items
+            .swap_with_unseen(i, unseen)
+            .ok_or(SwapMonsterError::NothingToSwap)?;
+        self.wounds.retain(|(m, _)| *m != monster);
+        self.game_event(ChatLine::GameEvent(format!(
+            "{:?} is swapped with {:?}",
+            monster, new
+        )))
+        .await;
+        Ok(())
+    }
+    async fn game_over(&mut self, outcome: server::GameOutcome) {
+        info!(?outcome, "Game over");
+        self.outcome = Some(outcome);
+        if let Some(snapshots) = &self.snapshots {
+            snapshots.remove(self.code);
+        }
+        self.stats.monsters_left =
+            self.monsters.items.cards.len() as u16 - self.stats.monsters_defeated;
+        self.broadcast_to_all(Msg::with(server::GameMsg::GameOver {
+            outcome,
+            stats: self.stats,
+        }))
+        .await;
+        self.broadcast_to_spectators(Msg::with(server::IntroMsg::Watch(self.game_view())))
+            .await;
+    }
+    async fn game_event(&mut self, event: ChatLine) {
+        self.state.chat.push(event.clone());
+        self.broadcast_to_all(Msg::from(event.clone())).await;
+        self.broadcast_to_spectators(Msg::from(event)).await;
+    }
+    async fn broadcast_to_spectators(&self, msg: Msg<SharedMsg, server::IntroMsg>) {
+        futures::stream::iter(self.spectators.iter())
+            .for_each_concurrent(MAX_PLAYER_COUNT, |s| s.peer.send_tcp(msg.clone()))
+            .await;
+    }
+    fn game_view(&self) -> server::GameView {
+        let active = self.active_player();
+        server::GameView {
+            monsters: self
+                .monsters
+                .active_items()
+                .into_iter()
+                .map(|i| i.copied())
+                .collect(),
+            players: self.players.clone(),
+            active_player: self
+                .peers()
+                .0
+                .iter()
+                .position(|p| p.addr == active)
+                .map(|i| self.players[i].name.clone())
+                .unwrap_or_default(),
+            phase: self.phase,
+            outcome: self.outcome,
+        }
+    }
+    // a finished game or a game with unknown states of peers is not saved
+    fn save_snapshot(&self) {
+        let Some(snapshots) = self.snapshots.as_ref().filter(|_| self.outcome.is_none()) else {
+            return;
+        };
+        let index = |addr: &PlayerId| self.peers().0.iter().position(|p| p.addr == *addr);
+        let Some(players) = self
+            .players
+            .iter()
+            .zip(self.peer_states.iter())
+            .map(|(view, state)| Some((view.clone(), state.clone()?)))
+            .collect::<Option<_>>()
+        else {
+            return;
+        };
+        snapshots.save(GameSnapshot {
+            code: self.code,
+            chat: self.state.chat.lines(),
+            monsters: self.monsters.items.clone(),
+            monster_line: self.monsters.actives.clone(),
+            active_player: self.state.peers.actives.clone(),
+            phase: self.phase,
+            wounds: self.wounds.clone(),
+            defeated: self.defeated.iter().filter_map(index).collect(),
+            shield: self
+                .shield
+                .and_then(|(p, shield)| Some((index(&p)?, shield))),
+            players,
+            stats: self.stats,
+            bots: self
+                .peers()
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| bot::is_bot(&p.addr))
+                .map(|(i, _)| i)
+                .collect(),
+        });
+    }
+    fn restore(game: GameSnapshot, handle: GameHandle, snapshots: Snapshots) -> Self {
+        // addresses of offline peers are replaced on reconnection
+        let addr = |i: usize| {
+            if game.bots.contains(&i) {
+                bot::addr(i as u16)
+            } else {
+                SocketAddr::from(([0, 0, 0, 0], i as u16))
+            }
+        };
+        let peers = game
+            .players
+            .iter()
+            .enumerate()
+            .map(|(i, (view, state))| {
+                let peer = peer::Peer {
+                    username: view.name.clone(),
+                    state: state.clone(),
+                };
+                if game.bots.contains(&i) {
+                    let peer =
+                        bot::restore_game(addr(i), peer, handle.clone(), Arc::new(bot::Greedy));
+                    PeerSlot::new(addr(i), view.name.clone(), (PeerStatus::Online, peer))
+                } else {
+                    let peer = peer::restore_game(addr(i), peer, handle.clone());
+                    PeerSlot::new(addr(i), view.name.clone(), (PeerStatus::Offline, peer))
+                }
+            })
+            .collect();
+        let mut peers = Stateble::with_items(Room::with_peers(peers));
+        peers.actives = game.active_player;
+        let mut monsters = Stateble::with_items(game.monsters);
+        monsters.actives = game.monster_line;
+        GameServer {
+            code: game.code,
+            state: StateServer {
+                chat: game.chat.into(),
+                peers,
+            },
+            monsters,
+            phase: game.phase,
+            wounds: game.wounds,
+            defeated: game.defeated.into_iter().map(addr).collect(),
+            shield: game.shield.map(|(i, shield)| (addr(i), shield)),
+            peer_states: game.players.iter().map(|(_, s)| Some(s.clone())).collect(),
+            players: game.players.into_iter().map(|(view, _)| view).collect(),
+            snapshots: Some(snapshots),
+            spectators: Default::default(),
+            stats: game.stats,
+            outcome: None,
+            logged_out: false,
+        }
+    }
+    fn active_player(&self) -> PlayerId {
+        self.state.peers.active_items()[0]
+            .as_ref()
+            .expect("Always have one active player")
+            .addr
+    }
+    // the strongest of surviving monsters attacks a defender
+    async fn attack_defender(&mut self, defender: PlayerId) {
+        let monster = self
+            .monsters
+            .active_items()
+            .into_iter()
+            .flatten()
+            .max_by_key(|m| m.rank as u16)
+            .copied();
+        let shield = match self.shield {
+            Some((paladin, shield)) if paladin != defender && monster.is_some() => {
+                self.shield = None;
+                shield
+            }
+            _ => 0,
+        };
+        debug!(?defender, ?monster, shield, "Monster attack");
+        self.state
+            .peers
+            .items
+            .get_peer(defender)
+            .expect("Must exists")
+            .peer
+            .1
+            .defend(monster, shield)
+            .await;
+    }
     fn switch_to_next_player(&mut self) -> PlayerId {
         match self.phase {
             GamePhaseKind::DropAbility => {
@@ -1311,19 +2398,22 @@ impl GameServer {
                     .deactivate_item_by_index(self.state.peers.actives[0].unwrap_index())
                     .expect("Must deactivate");
                 let _ = self.state.peers.next_actives().map_err(|eof| {
-                    // handle game end here?
                     tracing::info!("Next cycle");
-                    let _ = self.monsters.next_actives();
+                    self.stats.rounds += 1;
+                    // the monster deck is over and the last monsters are defeated
+                    if self.monsters.next_actives().is_err()
+                        && self.monsters.active_items().iter().all(Option::is_none)
+                    {
+                        self.outcome = Some(server::GameOutcome::Victory);
+                    }
+                    self.wounds.retain(|(m, _)| !m.regenerates());
                     tracing::info!("Next monsters {:?}", self.monsters.active_items());
                     self.phase = GamePhaseKind::DropAbility;
                     self.state.peers.repeat_after_eof(eof);
                 });
             }
         };
-        self.state.peers.active_items()[0]
-            .as_ref()
-            .expect("Always Some")
-            .addr
+        self.active_player()
     }
 }
 
@@ -1411,14 +2501,12 @@ where
         &self,
         msg: <PeerHandle<T> as SendSocketMessage>::Msg,
     ) -> Result<(), SendError> {
-        let _ = self
-            .peer
+        self.peer
             .1
             .tx
             .send(Msg::from(msg))
             .await
-            .map_err(|_| SendError);
-        Ok(())
+            .map_err(|_| SendError)
     }
     #[inline]
     fn can_send(&self) -> bool {